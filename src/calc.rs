@@ -0,0 +1,195 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::money::Money;
+
+/// Exact fraction used while evaluating, so "10/3*3" gives back 10.
+#[derive(Clone, Copy)]
+struct Ratio {
+    num: i128,
+    den: i128,
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Ratio {
+    fn new(num: i128, den: i128) -> Option<Self> {
+        if den == 0 {
+            return None;
+        }
+        let divisor = gcd(num, den) * den.signum();
+        Some(Self {
+            num: num / divisor,
+            den: den / divisor,
+        })
+    }
+
+    fn add(self, other: Self) -> Option<Self> {
+        let num = self
+            .num
+            .checked_mul(other.den)?
+            .checked_add(other.num.checked_mul(self.den)?)?;
+        Self::new(num, self.den.checked_mul(other.den)?)
+    }
+
+    fn mul(self, other: Self) -> Option<Self> {
+        Self::new(
+            self.num.checked_mul(other.num)?,
+            self.den.checked_mul(other.den)?,
+        )
+    }
+
+    fn neg(self) -> Self {
+        Self {
+            num: -self.num,
+            den: self.den,
+        }
+    }
+
+    fn inv(self) -> Option<Self> {
+        Self::new(self.den, self.num)
+    }
+
+    /// Rounds to the nearest cent, halves away from zero.
+    fn to_money(self) -> Option<Money> {
+        let scaled = self.num.checked_mul(200)? / self.den;
+        let cents = (scaled + scaled.signum()) / 2;
+        Some(Money::from_cents(i64::try_from(cents).ok()?))
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_spaces(&mut self) {
+        while matches!(self.chars.peek(), Some(c) if c.is_whitespace() || *c == '€') {
+            self.chars.next();
+        }
+    }
+
+    fn next_if_operator(&mut self, operators: &[char]) -> Option<char> {
+        self.skip_spaces();
+        self.chars.next_if(|c| operators.contains(c))
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Option<Ratio> {
+        let mut value = self.term()?;
+        while let Some(operator) = self.next_if_operator(&['+', '-']) {
+            let term = self.term()?;
+            value = match operator {
+                '+' => value.add(term)?,
+                _ => value.add(term.neg())?,
+            };
+        }
+        Some(value)
+    }
+
+    // term := factor (('*' | '/' | 'x') factor)*
+    fn term(&mut self) -> Option<Ratio> {
+        let mut value = self.factor()?;
+        while let Some(operator) = self.next_if_operator(&['*', '/', 'x']) {
+            let factor = self.factor()?;
+            value = match operator {
+                '/' => value.mul(factor.inv()?)?,
+                _ => value.mul(factor)?,
+            };
+        }
+        Some(value)
+    }
+
+    // factor := '-' factor | '(' expression ')' | number
+    fn factor(&mut self) -> Option<Ratio> {
+        if self.next_if_operator(&['-']).is_some() {
+            return Some(self.factor()?.neg());
+        }
+        if self.next_if_operator(&['(']).is_some() {
+            let value = self.expression()?;
+            self.next_if_operator(&[')'])?;
+            return Some(value);
+        }
+
+        self.skip_spaces();
+        let mut number = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
+        {
+            number.push(c);
+        }
+        let amount = Money::parse(&number)?;
+        Ratio::new(i128::from(amount.cents()), 100)
+    }
+}
+
+/// Evaluates an amount typed in chat, either a plain amount accepted by
+/// [`Money::parse`] or an expression like "45/3" or "(12,5+3.20)*2", rounded
+/// to the cent.
+pub fn evaluate(text: &str) -> Option<Money> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+    };
+    let value = parser.expression()?;
+    parser.skip_spaces();
+    if parser.chars.next().is_some() {
+        return None;
+    }
+    value.to_money()
+}
+
+/// Whether `text` is an expression rather than a plain amount, in which case
+/// the computed result is worth echoing back.
+pub fn is_expression(text: &str) -> bool {
+    Money::parse(text).is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cents(text: &str) -> Option<i64> {
+        evaluate(text).map(Money::cents)
+    }
+
+    #[test]
+    fn evaluates_plain_amounts() {
+        assert_eq!(cents("12,50"), Some(1250));
+        assert_eq!(cents("€ 12.50"), Some(1250));
+        assert!(!is_expression("12,50"));
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        assert_eq!(cents("45/3"), Some(1500));
+        assert_eq!(cents("(12,5+3.20)*2"), Some(3140));
+        assert_eq!(cents("10/3*3"), Some(1000));
+        assert_eq!(cents("3 x 2"), Some(600));
+        assert_eq!(cents("-(2-5)"), Some(300));
+        assert!(is_expression("45/3"));
+    }
+
+    #[test]
+    fn rounds_to_the_cent_halves_away_from_zero() {
+        assert_eq!(cents("10/3"), Some(333));
+        assert_eq!(cents("0.05/2"), Some(3));
+        assert_eq!(cents("-0.05/2"), Some(-3));
+    }
+
+    #[test]
+    fn refuses_broken_expressions() {
+        assert_eq!(cents("1/0"), None);
+        assert_eq!(cents("("), None);
+        assert_eq!(cents("(1+2"), None);
+        assert_eq!(cents("1+"), None);
+        assert_eq!(cents("2 3"), None);
+        assert_eq!(cents(""), None);
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::{Datelike, Local, Months, NaiveDate};
+use teloxide::{
+    payloads::EditMessageReplyMarkupSetters,
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot, RequestError,
+};
+
+static MONTH_NAMES: [&str; 12] = [
+    "Gennaio",
+    "Febbraio",
+    "Marzo",
+    "Aprile",
+    "Maggio",
+    "Giugno",
+    "Luglio",
+    "Agosto",
+    "Settembre",
+    "Ottobre",
+    "Novembre",
+    "Dicembre",
+];
+
+static WEEKDAYS: [&str; 7] = ["L", "M", "M", "G", "V", "S", "D"];
+
+static PREFIX: &str = "cal:";
+
+enum CalendarAction {
+    Pick(NaiveDate),
+    Show(NaiveDate),
+    Ignore,
+}
+
+fn button(text: impl Into<String>, action: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(text, format!("{}{}", PREFIX, action))
+}
+
+fn parse_action(data: &str) -> Option<CalendarAction> {
+    let action = data.strip_prefix(PREFIX)?;
+    if action == "ignore" {
+        return Some(CalendarAction::Ignore);
+    }
+    let (kind, date) = action.split_once(':')?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    match kind {
+        "pick" => Some(CalendarAction::Pick(date)),
+        "show" => Some(CalendarAction::Show(date)),
+        _ => None,
+    }
+}
+
+/// Month grid for the month containing `month`, with buttons to move to the
+/// previous and next month and a shortcut for `today`.
+pub fn keyboard(month: NaiveDate, today: NaiveDate) -> InlineKeyboardMarkup {
+    let first = month.with_day(1).unwrap();
+    let previous = first - Months::new(1);
+    let next = first + Months::new(1);
+
+    let mut rows = vec![
+        vec![
+            button("«", &format!("show:{}", previous)),
+            button(
+                format!("{} {}", MONTH_NAMES[first.month0() as usize], first.year()),
+                "ignore",
+            ),
+            button("»", &format!("show:{}", next)),
+        ],
+        WEEKDAYS.iter().map(|day| button(*day, "ignore")).collect(),
+    ];
+
+    let mut week = vec![button(" ", "ignore"); first.weekday().num_days_from_monday() as usize];
+    let mut day = first;
+    while day < next {
+        week.push(button(day.day().to_string(), &format!("pick:{}", day)));
+        if week.len() == 7 {
+            rows.push(std::mem::take(&mut week));
+        }
+        day = day.succ_opt().unwrap();
+    }
+    if !week.is_empty() {
+        week.resize(7, button(" ", "ignore"));
+        rows.push(week);
+    }
+
+    rows.push(vec![button("oggi", &format!("pick:{}", today))]);
+
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Handles a press on a calendar built by [`keyboard`]: moves between months
+/// in place and returns the date once one is picked.
+pub async fn handle_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+) -> Result<Option<NaiveDate>, RequestError> {
+    bot.answer_callback_query(q.id.clone()).await?;
+
+    let action = q.data.as_deref().and_then(parse_action);
+    match (action, &q.message) {
+        (Some(CalendarAction::Pick(date)), Some(message)) => {
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                format!("data: {}", date.format("%d/%m/%Y")),
+            )
+            .await?;
+            Ok(Some(date))
+        }
+        (Some(CalendarAction::Pick(date)), None) => Ok(Some(date)),
+        (Some(CalendarAction::Show(month)), Some(message)) => {
+            let today = Local::now().date_naive();
+            bot.edit_message_reply_markup(message.chat.id, message.id)
+                .reply_markup(keyboard(month, today))
+                .await?;
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
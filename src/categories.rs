@@ -0,0 +1,129 @@
+use crate::quick_entry;
+
+/// Separates a category from its subcategory in the list of categories, as
+/// in `Food/Restaurants`.
+const SEPARATOR: char = '/';
+
+/// Category and subcategory of an entry of the list, the latter empty for a
+/// top-level one.
+pub fn split(name: &str) -> (String, String) {
+    match name.split_once(SEPARATOR) {
+        Some((category, subcategory)) => {
+            (category.trim().to_string(), subcategory.trim().to_string())
+        }
+        None => (name.trim().to_string(), String::new()),
+    }
+}
+
+/// Entry of the list for a category and subcategory, the inverse of
+/// [`split`].
+pub fn join(category: &str, subcategory: &str) -> String {
+    if subcategory.is_empty() {
+        category.to_string()
+    } else {
+        format!("{}{}{}", category, SEPARATOR, subcategory)
+    }
+}
+
+/// Start of the entries of the subcategories of `category`.
+pub fn prefix(category: &str) -> String {
+    format!("{}{}", category, SEPARATOR)
+}
+
+/// Top-level categories in list order, including those only listed
+/// through their subcategories.
+pub fn parents(names: &[String]) -> Vec<String> {
+    let mut parents: Vec<String> = vec![];
+    for name in names {
+        let (category, _) = split(name);
+        if !parents.contains(&category) {
+            parents.push(category);
+        }
+    }
+    parents
+}
+
+/// Entries of the subcategories of `category`, in list order.
+pub fn children(names: &[String], category: &str) -> Vec<String> {
+    names
+        .iter()
+        .filter(|name| {
+            let (parent, subcategory) = split(name);
+            parent == category && !subcategory.is_empty()
+        })
+        .cloned()
+        .collect()
+}
+
+/// Category and subcategory written as `name` in a quick entry: a whole
+/// entry like `Food/Restaurants`, a top-level category, or a subcategory
+/// found under a single category.
+pub fn find(names: &[String], name: &str) -> Option<(String, String)> {
+    let mut options = parents(names);
+    options.extend(names.iter().cloned());
+    if let Some(found) = quick_entry::find_option(&options, name) {
+        return Some(split(&found));
+    }
+
+    let entries: Vec<&String> = names
+        .iter()
+        .filter(|entry| !split(entry).1.is_empty())
+        .collect();
+    let subcategories: Vec<String> = entries.iter().map(|entry| split(entry).1).collect();
+    let found = quick_entry::find_option(&subcategories, name)?;
+    let mut matching = entries.iter().filter(|entry| split(entry).1 == found);
+    match (matching.next(), matching.next()) {
+        (Some(entry), None) => Some(split(entry)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn found(category: &str, subcategory: &str) -> Option<(String, String)> {
+        Some((category.to_string(), subcategory.to_string()))
+    }
+
+    #[test]
+    fn splits_and_joins() {
+        assert_eq!(
+            split("Food / Restaurants"),
+            found("Food", "Restaurants").unwrap()
+        );
+        assert_eq!(split("Food"), found("Food", "").unwrap());
+        assert_eq!(join("Food", "Restaurants"), "Food/Restaurants");
+        assert_eq!(join("Food", ""), "Food");
+    }
+
+    #[test]
+    fn lists_parents_and_children() {
+        let list = names(&["Food/Restaurants", "Home", "Food/Groceries", "Food"]);
+        assert_eq!(parents(&list), names(&["Food", "Home"]));
+        assert_eq!(
+            children(&list, "Food"),
+            names(&["Food/Restaurants", "Food/Groceries"])
+        );
+        assert!(children(&list, "Home").is_empty());
+    }
+
+    #[test]
+    fn finds_entries_parents_and_unique_subcategories() {
+        let list = names(&["Food/Restaurants", "Food/Other", "Home/Other", "Transport"]);
+        assert_eq!(
+            find(&list, "food/restaurants"),
+            found("Food", "Restaurants")
+        );
+        assert_eq!(find(&list, "Food"), found("Food", ""));
+        assert_eq!(find(&list, "transport"), found("Transport", ""));
+        assert_eq!(find(&list, "Restaurants"), found("Food", "Restaurants"));
+        // under more than one category, a subcategory alone is ambiguous
+        assert_eq!(find(&list, "Other"), None);
+        assert_eq!(find(&list, "Leisure"), None);
+    }
+}
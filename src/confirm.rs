@@ -0,0 +1,66 @@
+use teloxide::{
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot, RequestError,
+};
+
+use crate::structs::Field;
+
+static PREFIX: &str = "confirm:";
+
+/// Choice made on the summary shown before an entry is written.
+pub enum Action {
+    Save,
+    Discard,
+    Edit(Field),
+}
+
+fn button(text: &str, action: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(text, format!("{}{}", PREFIX, action))
+}
+
+fn parse_action(data: &str) -> Option<Action> {
+    match data.strip_prefix(PREFIX)? {
+        "save" => Some(Action::Save),
+        "discard" => Some(Action::Discard),
+        action => Field::from_key(action.strip_prefix("edit:")?).map(Action::Edit),
+    }
+}
+
+/// Buttons under the summary: one to edit each of `fields`, then save and
+/// discard.
+pub fn keyboard(fields: &[Field]) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = fields
+        .chunks(3)
+        .map(|fields| {
+            fields
+                .iter()
+                .map(|field| {
+                    button(
+                        &format!("✏️ {}", field.label()),
+                        &format!("edit:{}", field.key()),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    rows.push(vec![
+        button("✅ salva", "save"),
+        button("🗑 scarta", "discard"),
+    ]);
+
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Handles a press on a [`keyboard`], removing the buttons so the summary
+/// can't be used twice.
+pub async fn handle_callback(bot: &Bot, q: &CallbackQuery) -> Result<Option<Action>, RequestError> {
+    bot.answer_callback_query(q.id.clone()).await?;
+
+    let action = q.data.as_deref().and_then(parse_action);
+    if let (Some(_), Some(message)) = (&action, &q.message) {
+        bot.edit_message_reply_markup(message.chat.id, message.id)
+            .await?;
+    }
+    Ok(action)
+}
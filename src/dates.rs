@@ -0,0 +1,75 @@
+use chrono::{Datelike, Duration, NaiveDate};
+
+pub static INVALID_DATE: &str = "data non valida, manda oggi, ieri, 12, 12/03 o 2026-03-12";
+
+/// Parses the date sent at the date step, relative to `today`.
+///
+/// Accepts "oggi"/"today", "ieri"/"yesterday", a day of the current month
+/// ("12"), "12/03", "12/03/2026" (or "12/03/26") and "2026-03-12". Days that
+/// don't exist, like 31/02, give `None`.
+pub fn parse_date(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let text = text.trim().to_lowercase();
+
+    match text.as_str() {
+        "oggi" | "today" => return Some(today),
+        "ieri" | "yesterday" => return Some(today - Duration::days(1)),
+        _ => {}
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(&text, "%Y-%m-%d") {
+        return Some(date);
+    }
+
+    let parts = text
+        .split('/')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+
+    match parts[..] {
+        [day] => NaiveDate::from_ymd_opt(today.year(), today.month(), day),
+        [day, month] => NaiveDate::from_ymd_opt(today.year(), month, day),
+        [day, month, year] if year < 100 => NaiveDate::from_ymd_opt(2000 + year as i32, month, day),
+        [day, month, year] => NaiveDate::from_ymd_opt(year as i32, month, day),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn reads_relative_days() {
+        let today = date(2026, 3, 1);
+        assert_eq!(parse_date("oggi", today), Some(today));
+        assert_eq!(parse_date(" Today ", today), Some(today));
+        assert_eq!(parse_date("ieri", today), Some(date(2026, 2, 28)));
+        assert_eq!(parse_date("yesterday", today), Some(date(2026, 2, 28)));
+    }
+
+    #[test]
+    fn reads_documented_forms() {
+        let today = date(2026, 10, 18);
+        assert_eq!(parse_date("12", today), Some(date(2026, 10, 12)));
+        assert_eq!(parse_date("12/03", today), Some(date(2026, 3, 12)));
+        assert_eq!(parse_date("12/03/2025", today), Some(date(2025, 3, 12)));
+        assert_eq!(parse_date("12/03/25", today), Some(date(2025, 3, 12)));
+        assert_eq!(parse_date("2026-03-12", today), Some(date(2026, 3, 12)));
+    }
+
+    #[test]
+    fn refuses_days_that_dont_exist() {
+        let today = date(2026, 10, 18);
+        assert_eq!(parse_date("31/02", today), None);
+        assert_eq!(parse_date("29/02/2026", today), None);
+        assert_eq!(parse_date("32", today), None);
+        assert_eq!(parse_date("12/13", today), None);
+        assert_eq!(parse_date("1/2/3/4", today), None);
+        assert_eq!(parse_date("domani", today), None);
+        assert_eq!(parse_date("", today), None);
+    }
+}
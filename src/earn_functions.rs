@@ -1,19 +1,222 @@
-use std::num::{ParseFloatError, ParseIntError};
+use std::sync::Arc;
 
-use teloxide::{requests::Requester, types::Message, Bot};
+use chrono::Local;
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{CallbackQuery, Message},
+    Bot,
+};
 
-use crate::{sheet_api, structs, HandlerResult, MainDialogue, MainState};
+use crate::{
+    calc, calendar, confirm, dates,
+    ledger::{EntryKind, SharedLedger},
+    linked_sheet, money, pay_functions,
+    picker::{self, Picked, Picker},
+    quick_entry, recent_functions, report_error,
+    settings::{ChatSettings, LastWrite, SettingsStore},
+    structs,
+    structs::Field,
+    write_queue::{QueuedEntry, WriteQueue},
+    HandlerResult, MainDialogue, MainState,
+};
 
-pub async fn guadagno(bot: Bot, dialogue: MainDialogue, msg: Message) -> HandlerResult {
-    let sheet_id: String = sheet_api::get_sheet_id(msg.chat.id);
-    if sheet_id == "null" || sheet_id.is_empty() {
-        bot.send_message(msg.chat.id, "manda link del foglio")
-            .await?;
-        dialogue.update(MainState::GetLink).await?;
-    } else {
+static RETRY: &str = "manda un messaggio qualsiasi per riprovare";
+
+/// Keyboard of the income categories, the ones the chat uses most first.
+fn category_picker(names: Vec<String>, chat: &ChatSettings) -> Picker {
+    Picker::new(picker::most_used_first(names, &chat.income_category_usage))
+        .columns(2)
+        .rows(6)
+        .emoji(chat.emoji.clone())
+}
+
+pub async fn guadagno(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+) -> HandlerResult {
+    if let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? {
         let g_data = Box::new(structs::GuadagnoStruct::new());
-        bot.send_message(msg.chat.id, "titolo guadagno").await?;
-        dialogue.update(MainState::GTitle { g_data }).await?;
+        ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
+    }
+    Ok(())
+}
+
+pub async fn guadagno_quick(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    args: String,
+) -> HandlerResult {
+    let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? else {
+        return Ok(());
+    };
+
+    let entry = quick_entry::parse(&args);
+    let mut g_data = Box::new(structs::GuadagnoStruct::new());
+
+    if !entry.title.is_empty() {
+        g_data.title = entry.title;
+        g_data.fill(Field::Title);
+    }
+    if let Some(amount) = entry.amount {
+        g_data.amount = amount;
+        g_data.fill(Field::Amount);
+    }
+    g_data.fill(Field::Date);
+    // a list that can't be read leaves the field to its step, which reports
+    // the failure
+    if let Some(category) = entry.category {
+        let categories = ledger
+            .income_categories(&sheet_data.sheet_id)
+            .await
+            .unwrap_or_default();
+        match quick_entry::find_option(&categories, &category) {
+            Some(category) => {
+                g_data.category = category;
+                g_data.fill(Field::Category);
+            }
+            None => {
+                bot.send_message(
+                    dialogue.chat_id(),
+                    format!("categoria {} non trovata", category),
+                )
+                .await?;
+            }
+        }
+    }
+    if let Some(wallet) = entry.wallet {
+        let wallets = ledger
+            .wallets(&sheet_data.sheet_id)
+            .await
+            .unwrap_or_default();
+        match quick_entry::find_option(&wallets, &wallet) {
+            Some(wallet) => {
+                g_data.wallet = wallet;
+                g_data.fill(Field::Wallet);
+            }
+            None => {
+                bot.send_message(dialogue.chat_id(), format!("wallet {} non trovato", wallet))
+                    .await?;
+            }
+        }
+    }
+    g_data.notes = entry.notes;
+    g_data.fill(Field::Notes);
+
+    ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
+    Ok(())
+}
+
+/// Asks for the first field of `g_data` still missing, or shows the summary
+/// to confirm once all of them are filled.
+pub async fn ask_next(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    mut g_data: Box<structs::GuadagnoStruct>,
+    sheet_data: Box<structs::SheetData>,
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+
+    // fields the ledger has no room for are neither asked nor shown
+    let values = ledger.stored_values(&sheet_data.sheet_id, EntryKind::Income);
+    *g_data = g_data.stored(values);
+
+    // a ledger without income categories writes incomes without one
+    if g_data.missing.first() == Some(&Field::Category)
+        && ledger
+            .income_categories(&sheet_data.sheet_id)
+            .await
+            .is_ok_and(|names| names.is_empty())
+    {
+        g_data.category.clear();
+        g_data.fill(Field::Category);
+    }
+
+    match g_data.missing.first().copied() {
+        Some(Field::Title) => {
+            bot.send_message(chat_id, "titolo guadagno").await?;
+            dialogue
+                .update(MainState::GTitle { g_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Amount) => {
+            bot.send_message(chat_id, "quanto").await?;
+            dialogue
+                .update(MainState::GAmount { g_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Date) => {
+            let today = Local::now().date_naive();
+            bot.send_message(chat_id, "data")
+                .reply_markup(calendar::keyboard(today, today))
+                .await?;
+            dialogue
+                .update(MainState::GDate { g_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Category) => {
+            let names = match ledger.income_categories(&sheet_data.sheet_id).await {
+                Ok(names) => names,
+                Err(error) => {
+                    report_error(&bot, chat_id, "non riesco a leggere le categorie", &error)
+                        .await?;
+                    bot.send_message(chat_id, RETRY).await?;
+                    dialogue
+                        .update(MainState::GCategory { g_data, sheet_data })
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let picker = category_picker(names, &settings.get(chat_id));
+            bot.send_message(chat_id, "categoria")
+                .reply_markup(picker.keyboard(0))
+                .await?;
+            dialogue
+                .update(MainState::GCategory { g_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Wallet) => {
+            let names = match ledger.wallets(&sheet_data.sheet_id).await {
+                Ok(names) => names,
+                Err(error) => {
+                    report_error(&bot, chat_id, "non riesco a leggere i wallet", &error).await?;
+                    bot.send_message(chat_id, RETRY).await?;
+                    dialogue
+                        .update(MainState::GWallet { g_data, sheet_data })
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let picker = pay_functions::wallet_picker(names, &settings.get(chat_id));
+            bot.send_message(chat_id, "wallet")
+                .reply_markup(picker.keyboard(0))
+                .await?;
+            dialogue
+                .update(MainState::GWallet { g_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Notes) => {
+            bot.send_message(chat_id, "note aggiuntive").await?;
+            dialogue
+                .update(MainState::GNotes { g_data, sheet_data })
+                .await?;
+        }
+        None => {
+            bot.send_message(chat_id, g_data.summary())
+                .reply_markup(confirm::keyboard(structs::GuadagnoStruct::stored_fields(
+                    values,
+                )))
+                .await?;
+            dialogue
+                .update(MainState::GConfirm { g_data, sheet_data })
+                .await?;
+        }
     }
     Ok(())
 }
@@ -21,14 +224,16 @@ pub async fn guadagno(bot: Bot, dialogue: MainDialogue, msg: Message) -> Handler
 pub async fn guadagno_title(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     msg: Message,
-    mut g_data: Box<structs::GuadagnoStruct>,
+    (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            bot.send_message(msg.chat.id, "quanto").await?;
             g_data.title = text.to_string();
-            dialogue.update(MainState::GAmount { g_data }).await?;
+            g_data.fill(Field::Title);
+            ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "manda un testo").await?;
@@ -40,53 +245,254 @@ pub async fn guadagno_title(
 pub async fn guadagno_amount(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     msg: Message,
-    mut g_data: Box<structs::GuadagnoStruct>,
+    (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
+) -> HandlerResult {
+    let text = msg.text().unwrap_or_default();
+    match calc::evaluate(text) {
+        Some(amount) => {
+            if calc::is_expression(text) {
+                bot.send_message(msg.chat.id, format!("{} = {}", text.trim(), amount))
+                    .await?;
+            }
+            g_data.amount = amount;
+            g_data.fill(Field::Amount);
+            ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, money::INVALID_AMOUNT).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn guadagno_date(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    msg: Message,
+    (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
 ) -> HandlerResult {
-    let value: Result<f32, ParseFloatError> = msg.text().unwrap().parse::<f32>();
-    let hub = sheet_api::api_init().await;
-    let sheet_id: String = sheet_api::get_sheet_id(msg.chat.id);
-    if sheet_id.is_empty() {
-        bot.send_message(msg.chat.id, "manda link del foglio")
-            .await?;
-        dialogue.exit().await?;
+    let today = Local::now().date_naive();
+    match msg.text().and_then(|text| dates::parse_date(text, today)) {
+        Some(date) => {
+            g_data.date = date;
+            g_data.fill(Field::Date);
+            ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, dates::INVALID_DATE).await?;
+        }
     }
-    let sheet_data = Box::new(structs::SheetData::new(hub, sheet_id));
+    Ok(())
+}
 
-    match value {
-        Ok(text) => {
-            bot.send_message(msg.chat.id, "data").await?;
-            g_data.amount = text;
-            dialogue
-                .update(MainState::GDate { g_data, sheet_data })
-                .await?;
+pub async fn guadagno_calendar(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    if let Some(date) = calendar::handle_callback(&bot, &q).await? {
+        g_data.date = date;
+        g_data.fill(Field::Date);
+        ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
+    }
+    Ok(())
+}
+
+pub async fn guadagno_category(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+    let names = match ledger.income_categories(&sheet_data.sheet_id).await {
+        Ok(names) => names,
+        Err(error) => {
+            bot.answer_callback_query(q.id).await?;
+            return report_error(&bot, chat_id, "non riesco a leggere le categorie", &error).await;
         }
-        Err(err) => {
-            dbg!(err);
-            bot.send_message(msg.chat.id, "manda un testo").await?;
+    };
+    let picked = category_picker(names, &settings.get(chat_id))
+        .handle_callback(&bot, &q)
+        .await?;
+    if let Some(Picked::Name(category)) = picked {
+        g_data.category = category;
+        g_data.fill(Field::Category);
+        ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn guadagno_wallet(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+    let names = match ledger.wallets(&sheet_data.sheet_id).await {
+        Ok(names) => names,
+        Err(error) => {
+            bot.answer_callback_query(q.id).await?;
+            return report_error(&bot, chat_id, "non riesco a leggere i wallet", &error).await;
         }
+    };
+    let picked = pay_functions::wallet_picker(names, &settings.get(chat_id))
+        .handle_callback(&bot, &q)
+        .await?;
+    if let Some(Picked::Name(wallet)) = picked {
+        g_data.wallet = wallet;
+        g_data.fill(Field::Wallet);
+        ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
     }
+
     Ok(())
 }
-pub async fn guadagno_date(
+
+pub async fn guadagno_notes(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     msg: Message,
     (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
 ) -> HandlerResult {
-    let value: Result<u8, ParseIntError> = msg.text().unwrap().parse::<u8>();
-    match value {
-        Ok(text) => {
-            bot.send_message(msg.chat.id, "finito").await?;
-            g_data.date = text;
-            dbg!(&g_data);
-            sheet_api::write_guadagno_data(&sheet_data.sheet, &sheet_data.sheet_id, g_data).await;
-            dialogue.exit().await?;
+    match msg.text() {
+        Some(text) => {
+            g_data.notes = text.to_string();
+            g_data.fill(Field::Notes);
+            ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
         }
-        Err(err) => {
-            dbg!(err);
+        None => {
             bot.send_message(msg.chat.id, "manda un testo").await?;
         }
     }
     Ok(())
 }
+
+/// Asks again for the current step after reading its options failed.
+pub async fn guadagno_retry(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    (g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
+) -> HandlerResult {
+    ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await
+}
+
+pub async fn guadagno_confirm(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    queue: Arc<WriteQueue>,
+    (mut g_data, sheet_data): (Box<structs::GuadagnoStruct>, Box<structs::SheetData>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    let values = ledger.stored_values(&sheet_data.sheet_id, EntryKind::Income);
+    match confirm::handle_callback(&bot, &q).await? {
+        Some(confirm::Action::Save) => {
+            log::debug!("saving {:?}", g_data);
+            let saved = match &g_data.recorded {
+                // other changes may have shifted the row since it was read
+                Some(entry) => match recent_functions::unchanged(
+                    &ledger,
+                    &sheet_data.sheet_id,
+                    entry,
+                    &g_data.recorded_summary,
+                )
+                .await
+                {
+                    Ok(true) => ledger
+                        .update_income(&sheet_data.sheet_id, entry, &g_data)
+                        .await
+                        .map(|()| None),
+                    Ok(false) => {
+                        bot.send_message(
+                            dialogue.chat_id(),
+                            "il movimento è cambiato nel frattempo, modifica scartata: usa di nuovo /recent",
+                        )
+                        .await?;
+                        dialogue.exit().await?;
+                        return Ok(());
+                    }
+                    Err(error) => Err(error),
+                },
+                None => ledger
+                    .write_income(&sheet_data.sheet_id, &g_data)
+                    .await
+                    .map(Some),
+            };
+            match saved {
+                Ok(written) => {
+                    if let Some(entry) = written {
+                        settings.update(dialogue.chat_id(), |chat| {
+                            let summary = g_data.stored(values).summary();
+                            chat.last_write = Some(LastWrite::new(entry, summary));
+                            chat.record_income_use(&g_data.category, &g_data.wallet);
+                        })?;
+                    }
+                    let text = match g_data.recorded {
+                        Some(_) => "aggiornato",
+                        None => "finito",
+                    };
+                    bot.send_message(dialogue.chat_id(), text).await?;
+                    dialogue.exit().await?;
+                }
+                // a new entry is kept to be written once the ledger is back
+                Err(error) if error.is_transient() && g_data.recorded.is_none() => {
+                    log::warn!("guadagno queued: {}", error);
+                    queue.push(
+                        dialogue.chat_id(),
+                        &sheet_data.sheet_id,
+                        QueuedEntry::Income(*g_data),
+                    )?;
+                    bot.send_message(
+                        dialogue.chat_id(),
+                        "⏳ il foglio non risponde, il guadagno è in coda e verrà salvato appena possibile",
+                    )
+                    .await?;
+                    dialogue.exit().await?;
+                }
+                Err(error) => {
+                    report_error(&bot, dialogue.chat_id(), "salvataggio non riuscito", &error)
+                        .await?;
+                    // the press removed the buttons, so the summary comes back to retry
+                    bot.send_message(dialogue.chat_id(), g_data.summary())
+                        .reply_markup(confirm::keyboard(structs::GuadagnoStruct::stored_fields(
+                            values,
+                        )))
+                        .await?;
+                }
+            }
+        }
+        Some(confirm::Action::Discard) => {
+            let text = match g_data.recorded {
+                Some(_) => "modifica scartata",
+                None => "guadagno scartato",
+            };
+            bot.send_message(dialogue.chat_id(), text).await?;
+            dialogue.exit().await?;
+        }
+        Some(confirm::Action::Edit(field)) => {
+            g_data.missing = vec![field];
+            ask_next(bot, dialogue, ledger, settings, g_data, sheet_data).await?;
+        }
+        None => {}
+    }
+    Ok(())
+}
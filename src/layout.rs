@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{Datelike, NaiveDate};
+use serde::Deserialize;
+
+use crate::ledger::{EntryKind, ListKind};
+
+/// Where the bot reads and writes inside a spreadsheet. Keys left out of
+/// the layout file take the values of the original template, with tabs
+/// named like `October 2026`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    /// Name of each month, January first, used for `{month}` in
+    /// `tab_pattern`.
+    pub months: Vec<String>,
+    /// Name of the tab of a month: `{month}` stands for its name in
+    /// `months`, `{mm}` for its two-digit number and `{year}` for the year.
+    pub tab_pattern: String,
+    /// Tab copied to create the tab of a month that doesn't have one yet.
+    pub template_tab: String,
+    pub expenses: Columns,
+    pub incomes: Columns,
+    /// Range listing the categories, like `Categories!B4:B`. Leaving out
+    /// the last row lets the list grow.
+    pub categories: String,
+    /// Range listing the wallets.
+    pub wallets: String,
+    /// Range listing the categories of the incomes, which can be left empty.
+    pub income_categories: String,
+    /// Range of the archived categories, created when the first is archived.
+    pub archived_categories: String,
+    pub archived_wallets: String,
+    pub archived_income_categories: String,
+}
+
+/// Columns of the month tabs holding one kind of entry, in the order the
+/// fields are written. Fields past the last column are not asked for.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Columns {
+    pub first: String,
+    pub last: String,
+    /// Row of the first entry, below the headers.
+    pub first_row: u32,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            months: [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+            tab_pattern: "{month} {year}".to_string(),
+            template_tab: "Template".to_string(),
+            expenses: Columns::new("B", "G", 4),
+            incomes: Columns::new("I", "K", 4),
+            categories: "Categories!B4:B".to_string(),
+            wallets: "Categories!G4:G".to_string(),
+            income_categories: "Categories!L4:L".to_string(),
+            archived_categories: "Archive!A2:A".to_string(),
+            archived_wallets: "Archive!B2:B".to_string(),
+            archived_income_categories: "Archive!C2:C".to_string(),
+        }
+    }
+}
+
+impl Layout {
+    /// Name of the monthly tab the entries dated `date` go into.
+    pub fn month_tab(&self, date: NaiveDate) -> String {
+        self.tab_pattern
+            .replace("{month}", &self.months[date.month0() as usize])
+            .replace("{mm}", &format!("{:02}", date.month()))
+            .replace("{year}", &date.year().to_string())
+    }
+
+    pub fn columns(&self, kind: EntryKind) -> &Columns {
+        match kind {
+            EntryKind::Expense => &self.expenses,
+            EntryKind::Income => &self.incomes,
+        }
+    }
+
+    /// Ranges of the names in use and of the archived ones.
+    pub fn list_ranges(&self, kind: ListKind) -> (&str, &str) {
+        match kind {
+            ListKind::Categories => (&self.categories, &self.archived_categories),
+            ListKind::Wallets => (&self.wallets, &self.archived_wallets),
+            ListKind::IncomeCategories => {
+                (&self.income_categories, &self.archived_income_categories)
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.months.len() != 12 {
+            return Err(format!(
+                "months needs 12 names, found {}",
+                self.months.len()
+            ));
+        }
+        for columns in [&self.expenses, &self.incomes] {
+            if column_index(&columns.first).is_none() || column_index(&columns.last).is_none() {
+                return Err(format!(
+                    "{}:{} is not a column range",
+                    columns.first, columns.last
+                ));
+            }
+            // title, amount and day are always written
+            if columns.width() < 3 {
+                return Err(format!(
+                    "{}:{} needs at least 3 columns",
+                    columns.first, columns.last
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Columns {
+    fn new(first: &str, last: &str, first_row: u32) -> Self {
+        Self {
+            first: first.to_string(),
+            last: last.to_string(),
+            first_row,
+        }
+    }
+
+    /// Zero-based index of the first column and the one after the last, as
+    /// structural requests expect them.
+    pub fn indices(&self) -> (i32, i32) {
+        let first = column_index(&self.first).unwrap_or_default();
+        let last = column_index(&self.last).unwrap_or_default();
+        (first, last + 1)
+    }
+
+    /// How many values of an entry the columns hold.
+    pub fn width(&self) -> usize {
+        let (first, end) = self.indices();
+        (end - first).max(0) as usize
+    }
+}
+
+/// Zero-based index of a column given by its letters, so `A` is 0 and `AA`
+/// is 26.
+fn column_index(letters: &str) -> Option<i32> {
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let number = letters
+        .to_ascii_uppercase()
+        .bytes()
+        .fold(0, |number, letter| number * 26 + (letter - b'A') as i32 + 1);
+    Some(number - 1)
+}
+
+/// Letters of a zero-based column index, the inverse of `column_index`.
+pub fn column_letters(index: i32) -> String {
+    let mut letters = Vec::new();
+    let mut number = index + 1;
+    while number > 0 {
+        letters.push(b'A' + ((number - 1) % 26) as u8);
+        number = (number - 1) / 26;
+    }
+    letters.iter().rev().map(|&letter| letter as char).collect()
+}
+
+/// Tab a range like `Categories!B4:B20` or `'My list'!A2:A` refers to.
+pub fn range_tab(range: &str) -> String {
+    let Some(quoted) = range.strip_prefix('\'') else {
+        return range
+            .split_once('!')
+            .map_or(range, |(tab, _)| tab)
+            .to_string();
+    };
+    let mut tab = String::new();
+    let mut chars = quoted.chars().peekable();
+    while let Some(c) = chars.next() {
+        // a quote ends the name, unless doubled to stand for itself
+        if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+            break;
+        }
+        tab.push(c);
+    }
+    tab
+}
+
+/// `tab` as written at the start of an A1 range: quoted, since month tabs
+/// have spaces in their names, with its own quotes doubled.
+pub fn quote_tab(tab: &str) -> String {
+    format!("'{}'", tab.replace('\'', "''"))
+}
+
+/// Contents of the layout file: the layout of every spreadsheet at the top
+/// level, replaced for single spreadsheets by a `[sheets.<id>]` table.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LayoutConfig {
+    #[serde(flatten)]
+    pub default: Layout,
+    #[serde(default)]
+    pub sheets: HashMap<String, Layout>,
+}
+
+impl LayoutConfig {
+    /// Reads the layout file at `path`, or the original template's layout if
+    /// there is none.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        };
+        let config: Self =
+            toml::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))?;
+        config.default.validate().map_err(invalid)?;
+        for (sheet_id, layout) in &config.sheets {
+            layout
+                .validate()
+                .map_err(|e| invalid(format!("[sheets.{}] {}", sheet_id, e)))?;
+        }
+        Ok(config)
+    }
+
+    pub fn for_sheet(&self, sheet_id: &str) -> &Layout {
+        self.sheets.get(sheet_id).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_columns_both_ways() {
+        for (index, letters) in [
+            (0, "A"),
+            (7, "H"),
+            (25, "Z"),
+            (26, "AA"),
+            (701, "ZZ"),
+            (702, "AAA"),
+        ] {
+            assert_eq!(column_letters(index), letters);
+            assert_eq!(column_index(letters), Some(index));
+        }
+        assert_eq!(column_index("aa"), Some(26));
+        assert_eq!(column_index(""), None);
+        assert_eq!(column_index("B4"), None);
+    }
+
+    #[test]
+    fn finds_the_tab_of_a_range() {
+        assert_eq!(range_tab("Categories!B4:B20"), "Categories");
+        assert_eq!(range_tab("'My list'!A2:A"), "My list");
+        assert_eq!(range_tab("Archive"), "Archive");
+        assert_eq!(range_tab("'Kate''s list'!A2:A"), "Kate's list");
+    }
+
+    #[test]
+    fn quotes_tabs_in_ranges() {
+        assert_eq!(quote_tab("October 2026"), "'October 2026'");
+        assert_eq!(quote_tab("Kate's"), "'Kate''s'");
+        let range = format!("{}!A1", quote_tab("Kate's list"));
+        assert_eq!(range_tab(&range), "Kate's list");
+    }
+
+    #[test]
+    fn names_month_tabs() {
+        let layout = Layout::default();
+        let date = NaiveDate::from_ymd_opt(2026, 3, 12).unwrap();
+        assert_eq!(layout.month_tab(date), "March 2026");
+        let layout = Layout {
+            tab_pattern: "{year}-{mm}".to_string(),
+            ..Layout::default()
+        };
+        assert_eq!(layout.month_tab(date), "2026-03");
+    }
+
+    #[test]
+    fn reads_a_partial_layout() {
+        let config: LayoutConfig = toml::from_str(
+            "tab_pattern = \"{month}\"\n[sheets.abc.expenses]\nfirst = \"A\"\nlast = \"F\"\nfirst_row = 2",
+        )
+        .unwrap();
+        assert_eq!(config.default.tab_pattern, "{month}");
+        assert_eq!(config.default.template_tab, "Template");
+        assert_eq!(config.for_sheet("abc").expenses.indices(), (0, 6));
+        assert_eq!(config.for_sheet("other").expenses.indices(), (1, 7));
+        assert_eq!(config.for_sheet("other").incomes.width(), 3);
+    }
+
+    #[test]
+    fn rejects_columns_too_narrow() {
+        let layout = Layout {
+            incomes: Columns::new("I", "J", 4),
+            ..Layout::default()
+        };
+        assert!(layout.validate().is_err());
+        assert!(Layout::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::{layout, sheet_api, sqlite_ledger, structs};
+
+/// What went wrong talking to the ledger. The message is shown in chat, so
+/// it's in Italian like the rest of the bot.
+#[derive(Debug)]
+pub enum LedgerError {
+    Sheets(Box<google_sheets4::Error>),
+    Sqlite(rusqlite::Error),
+    /// The spreadsheet has no tab with this name.
+    MissingTab(String),
+    /// The spreadsheet already has a tab /setup would create.
+    TabExists(String),
+    /// Google Sheets answered without something the request should return.
+    UnexpectedResponse(&'static str),
+    /// A cell of an entry the bot can't read back, like an amount in a
+    /// format of the sheet's own. Writing the entry back would lose it.
+    UnreadableCell(String),
+}
+
+pub type LedgerResult<T> = Result<T, LedgerError>;
+
+impl LedgerError {
+    /// Whether the same request may work later: the ledger couldn't be
+    /// reached, or answered with a rate limit or a server error. Rejected
+    /// requests are not worth retrying.
+    pub fn is_transient(&self) -> bool {
+        let LedgerError::Sheets(e) = self else {
+            return false;
+        };
+        match &**e {
+            google_sheets4::Error::HttpError(_)
+            | google_sheets4::Error::Io(_)
+            | google_sheets4::Error::MissingToken(_)
+            | google_sheets4::Error::Cancelled => true,
+            // every error status with a JSON body ends up here, 429 and 503 too
+            google_sheets4::Error::BadRequest(body) => body
+                .get("error")
+                .and_then(|error| error.get("code"))
+                .and_then(|code| code.as_u64())
+                .is_some_and(transient_status),
+            google_sheets4::Error::Failure(response) => {
+                transient_status(response.status().as_u16().into())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether an HTTP status says to try again later rather than that the
+/// request is wrong.
+fn transient_status(code: u64) -> bool {
+    code == 408 || code == 429 || (500..600).contains(&code)
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Sheets(e) => write!(f, "errore di Google Sheets: {}", e),
+            LedgerError::Sqlite(e) => write!(f, "errore del database: {}", e),
+            LedgerError::MissingTab(tab) => write!(f, "il foglio non ha la scheda {}", tab),
+            LedgerError::TabExists(tab) => write!(f, "il foglio ha già la scheda {}", tab),
+            LedgerError::UnexpectedResponse(what) => {
+                write!(f, "risposta inattesa da Google Sheets: manca {}", what)
+            }
+            LedgerError::UnreadableCell(cell) => {
+                write!(f, "non riesco a leggere {}, correggilo nel foglio", cell)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LedgerError::Sheets(e) => Some(e.as_ref()),
+            LedgerError::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<google_sheets4::Error> for LedgerError {
+    fn from(e: google_sheets4::Error) -> Self {
+        LedgerError::Sheets(Box::new(e))
+    }
+}
+
+impl From<rusqlite::Error> for LedgerError {
+    fn from(e: rusqlite::Error) -> Self {
+        LedgerError::Sqlite(e)
+    }
+}
+
+/// Names the chat picks from when writing an entry. Wallets are shared by
+/// expenses and incomes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ListKind {
+    Categories,
+    Wallets,
+    IncomeCategories,
+}
+
+/// Categories or wallets as kept in the ledger: the names offered, in
+/// order, and the archived ones, which older entries may still use.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NameList {
+    pub active: Vec<String>,
+    pub archived: Vec<String>,
+}
+
+/// Categories a new ledger starts with.
+pub static DEFAULT_CATEGORIES: [&str; 7] = [
+    "Food",
+    "Transport",
+    "Home",
+    "Health",
+    "Leisure",
+    "Shopping",
+    "Other",
+];
+
+/// Wallets a new ledger starts with.
+pub static DEFAULT_WALLETS: [&str; 3] = ["Cash", "Card", "Bank"];
+
+/// Income categories a new ledger starts with.
+pub static DEFAULT_INCOME_CATEGORIES: [&str; 4] = ["Salary", "Refunds", "Gifts", "Other"];
+
+/// Backend shared by every handler, built once in `main` and injected as a
+/// dependency.
+pub type SharedLedger = Arc<dyn LedgerBackend>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    Expense,
+    Income,
+}
+
+/// Location of an entry already written: the row of its month tab for
+/// Google Sheets, the row id for SQLite.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryRef {
+    pub kind: EntryKind,
+    /// First day of the month the entry is filed under.
+    pub month: NaiveDate,
+    pub row: i64,
+}
+
+impl EntryRef {
+    pub fn new(kind: EntryKind, date: NaiveDate, row: i64) -> Self {
+        Self {
+            kind,
+            month: date.with_day(1).unwrap_or(date),
+            row,
+        }
+    }
+
+    /// Short form used in callback data, like `p:2026-10:12`.
+    pub fn key(&self) -> String {
+        let kind = match self.kind {
+            EntryKind::Expense => "p",
+            EntryKind::Income => "g",
+        };
+        format!("{}:{}:{}", kind, self.month.format("%Y-%m"), self.row)
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        let mut parts = key.split(':');
+        let kind = match parts.next()? {
+            "p" => EntryKind::Expense,
+            "g" => EntryKind::Income,
+            _ => return None,
+        };
+        let month = NaiveDate::parse_from_str(&format!("{}-01", parts.next()?), "%Y-%m-%d").ok()?;
+        let row = parts.next()?.parse().ok()?;
+        Some(Self { kind, month, row })
+    }
+}
+
+/// Storage the dialogue writes entries to. `target` identifies the ledger
+/// inside the backend (the spreadsheet id for Google Sheets). Writes return
+/// where the entry went.
+#[async_trait]
+pub trait LedgerBackend: Send + Sync {
+    async fn write_expense(
+        &self,
+        target: &str,
+        data: &structs::PagamentoStruct,
+    ) -> LedgerResult<EntryRef>;
+
+    async fn write_income(
+        &self,
+        target: &str,
+        data: &structs::GuadagnoStruct,
+    ) -> LedgerResult<EntryRef>;
+
+    /// Latest expenses, newest first.
+    async fn recent_expenses(
+        &self,
+        target: &str,
+        limit: usize,
+    ) -> LedgerResult<Vec<(EntryRef, structs::PagamentoStruct)>>;
+
+    /// Latest incomes, newest first.
+    async fn recent_incomes(
+        &self,
+        target: &str,
+        limit: usize,
+    ) -> LedgerResult<Vec<(EntryRef, structs::GuadagnoStruct)>>;
+
+    async fn expense(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+    ) -> LedgerResult<Option<structs::PagamentoStruct>>;
+
+    async fn income(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+    ) -> LedgerResult<Option<structs::GuadagnoStruct>>;
+
+    async fn update_expense(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+        data: &structs::PagamentoStruct,
+    ) -> LedgerResult<()>;
+
+    async fn update_income(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+        data: &structs::GuadagnoStruct,
+    ) -> LedgerResult<()>;
+
+    async fn delete_entry(&self, target: &str, entry: &EntryRef) -> LedgerResult<()>;
+
+    /// How many values of an entry of `kind`, in the order they are written,
+    /// `target` has room for. The fields past them are neither asked nor
+    /// written.
+    fn stored_values(&self, target: &str, kind: EntryKind) -> usize;
+
+    async fn categories(&self, target: &str) -> LedgerResult<Vec<String>>;
+
+    async fn wallets(&self, target: &str) -> LedgerResult<Vec<String>>;
+
+    /// Categories of the incomes, empty when the ledger has none.
+    async fn income_categories(&self, target: &str) -> LedgerResult<Vec<String>>;
+
+    /// Categories or wallets, archived ones included.
+    async fn list(&self, target: &str, kind: ListKind) -> LedgerResult<NameList>;
+
+    /// Replaces the categories or wallets with `list`.
+    async fn save_list(&self, target: &str, kind: ListKind, list: &NameList) -> LedgerResult<()>;
+
+    /// Forgets what was cached about `target`, so the next reads see the
+    /// changes made outside the bot.
+    async fn refresh(&self, target: &str);
+
+    /// Checks that `target` (as sent by the user with /link) points to a
+    /// usable ledger, returning what is wrong with it for the chat.
+    async fn validate_target(&self, target: &str) -> LedgerResult<Vec<String>>;
+
+    /// Prepares `target` with what the bot needs to write into it.
+    async fn set_up(&self, target: &str) -> LedgerResult<()>;
+}
+
+/// Backend chosen with the `LEDGER_BACKEND` environment variable
+/// (`sheets`, the default, or `sqlite`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Sheets,
+    Sqlite,
+}
+
+impl BackendKind {
+    pub fn from_env() -> Self {
+        match std::env::var("LEDGER_BACKEND").as_deref() {
+            Ok("sqlite") => Self::Sqlite,
+            _ => Self::Sheets,
+        }
+    }
+
+    /// Whether a chat has to send a /link before writing entries.
+    pub fn requires_target(self) -> bool {
+        self == Self::Sheets
+    }
+}
+
+pub async fn connect() -> Result<SharedLedger, Box<dyn std::error::Error + Send + Sync>> {
+    match BackendKind::from_env() {
+        BackendKind::Sheets => {
+            let path = std::env::var("LAYOUT_PATH").unwrap_or_else(|_| "layout.toml".to_string());
+            let layouts = layout::LayoutConfig::load(Path::new(&path))?;
+            Ok(Arc::new(sheet_api::SheetsLedger::new(
+                sheet_api::api_init().await,
+                layouts,
+            )))
+        }
+        BackendKind::Sqlite => {
+            let path =
+                std::env::var("SQLITE_LEDGER_PATH").unwrap_or_else(|_| "ledger.sqlite".to_string());
+            Ok(Arc::new(sqlite_ledger::SqliteLedger::open(&path)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bad_request(code: u64) -> LedgerError {
+        let body = serde_json::json!({ "error": { "code": code, "message": "" } });
+        LedgerError::Sheets(Box::new(google_sheets4::Error::BadRequest(body)))
+    }
+
+    #[test]
+    fn transient_by_status() {
+        assert!(bad_request(429).is_transient());
+        assert!(bad_request(503).is_transient());
+        assert!(!bad_request(400).is_transient());
+        assert!(!bad_request(403).is_transient());
+        assert!(!LedgerError::MissingTab("Ottobre".to_string()).is_transient());
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use teloxide::{requests::Requester, Bot};
+
+use crate::{
+    categories,
+    ledger::{ListKind, NameList, SharedLedger},
+    linked_sheet, picker, report_error,
+    settings::SettingsStore,
+    HandlerResult, MainDialogue,
+};
+
+/// Change asked with the arguments of /categories or /wallets, like
+/// `rinomina Food > Groceries`.
+enum Change {
+    Add(String),
+    Rename(String, String),
+    /// Name and its new position, counting from 1.
+    Move(String, usize),
+    Archive(String),
+    Restore(String),
+    /// Emoji shown on the keyboards, or none to remove it.
+    Emoji(String, Option<String>),
+}
+
+fn parse_change(args: &str) -> Option<Change> {
+    let (verb, rest) = args.trim().split_once(char::is_whitespace)?;
+    let rest = rest.trim();
+    let change = match verb.to_lowercase().as_str() {
+        "aggiungi" => Change::Add(rest.to_string()),
+        "rinomina" => {
+            let (name, new_name) = rest.split_once('>')?;
+            let new_name = new_name.trim();
+            if new_name.is_empty() {
+                return None;
+            }
+            Change::Rename(name.trim().to_string(), new_name.to_string())
+        }
+        "sposta" => {
+            let (name, position) = rest.rsplit_once(char::is_whitespace)?;
+            Change::Move(name.trim().to_string(), position.parse().ok()?)
+        }
+        "archivia" => Change::Archive(rest.to_string()),
+        "ripristina" => Change::Restore(rest.to_string()),
+        // a last word without letters or digits is the emoji
+        "emoji" => match rest.rsplit_once(char::is_whitespace) {
+            Some((name, emoji)) if !emoji.chars().any(char::is_alphanumeric) => {
+                Change::Emoji(name.trim().to_string(), Some(emoji.to_string()))
+            }
+            _ => Change::Emoji(rest.to_string(), None),
+        },
+        _ => return None,
+    };
+    Some(change)
+}
+
+/// Index of `name` in `names`, ignoring case.
+fn position(names: &[String], name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    names.iter().position(|other| other.to_lowercase() == name)
+}
+
+/// Name as written in `list`, active or archived, of `name`.
+fn find(list: &NameList, name: &str) -> Option<String> {
+    let names = || list.active.iter().chain(&list.archived);
+    let name = name.to_lowercase();
+    names().find(|other| other.to_lowercase() == name).cloned()
+}
+
+/// Indexes in `names` of the subcategories of `category`, ignoring case.
+fn children(kind: ListKind, names: &[String], category: &str) -> Vec<usize> {
+    if kind != ListKind::Categories {
+        return vec![];
+    }
+    let category = category.to_lowercase();
+    (0..names.len())
+        .filter(|&i| {
+            let (parent, subcategory) = categories::split(&names[i]);
+            parent.to_lowercase() == category && !subcategory.is_empty()
+        })
+        .collect()
+}
+
+/// Applies `change` to `list`, or says why it can't be done. A category
+/// renamed or archived takes its subcategories along; the names renamed are
+/// returned with their new ones.
+fn apply(
+    kind: ListKind,
+    list: &mut NameList,
+    change: Change,
+) -> Result<Vec<(String, String)>, String> {
+    let missing = |name: &str| format!("{} non c'è", name);
+    let archived = |name: &str| format!("{} è in archivio, usa ripristina", name);
+    // the name goes in the data of its button
+    let too_long = |name: &str| name.len() > picker::MAX_NAME_LEN;
+
+    match change {
+        Change::Add(name) if too_long(&name) => return Err("nome troppo lungo".to_string()),
+        Change::Rename(_, new_name) if too_long(&new_name) => {
+            return Err("nome troppo lungo".to_string())
+        }
+        Change::Add(name) => {
+            if position(&list.active, &name).is_some() {
+                return Err(format!("{} c'è già", name));
+            }
+            if position(&list.archived, &name).is_some() {
+                return Err(archived(&name));
+            }
+            list.active.push(name);
+        }
+        Change::Rename(name, new_name) => {
+            let index = position(&list.active, &name);
+            let children = children(kind, &list.active, &name);
+            if index.is_none() && children.is_empty() {
+                return Err(missing(&name));
+            }
+            // changing only the case of a name is fine
+            if position(&list.active, &new_name).is_some_and(|other| Some(other) != index) {
+                return Err(format!("{} c'è già", new_name));
+            }
+            if position(&list.archived, &new_name).is_some() {
+                return Err(archived(&new_name));
+            }
+
+            let mut renamed = vec![];
+            if let Some(index) = index {
+                renamed.push((index, new_name.clone()));
+            }
+            for i in children {
+                let (_, subcategory) = categories::split(&list.active[i]);
+                let child = categories::join(&new_name, &subcategory);
+                if too_long(&child) {
+                    return Err(format!("{}: nome troppo lungo", child));
+                }
+                renamed.push((i, child));
+            }
+            let parent = match index {
+                Some(_) => None,
+                // a category listed only through its subcategories has its
+                // usage and emoji under its own name too
+                None => Some((categories::split(&list.active[renamed[0].0]).0, new_name)),
+            };
+            let mut renamed: Vec<(String, String)> = renamed
+                .into_iter()
+                .map(|(i, new_name)| {
+                    let name = std::mem::replace(&mut list.active[i], new_name.clone());
+                    (name, new_name)
+                })
+                .collect();
+            renamed.extend(parent);
+            return Ok(renamed);
+        }
+        Change::Move(name, new_position) => {
+            let index = position(&list.active, &name).ok_or_else(|| missing(&name))?;
+            let moved = list.active.remove(index);
+            let new_index = new_position.clamp(1, list.active.len() + 1) - 1;
+            list.active.insert(new_index, moved);
+        }
+        Change::Archive(name) => {
+            let mut indexes = children(kind, &list.active, &name);
+            indexes.extend(position(&list.active, &name));
+            if indexes.is_empty() {
+                return Err(missing(&name));
+            }
+            if indexes.len() == list.active.len() {
+                return Err("deve restarne almeno uno da scegliere".to_string());
+            }
+            indexes.sort_unstable();
+            for i in indexes.into_iter().rev() {
+                let name = list.active.remove(i);
+                list.archived.push(name);
+            }
+        }
+        Change::Restore(name) => {
+            let index = position(&list.archived, &name)
+                .ok_or_else(|| format!("{} non è in archivio", name))?;
+            list.active.push(list.archived.remove(index));
+        }
+        // kept in the chat settings, not in the list
+        Change::Emoji(..) => {}
+    }
+    Ok(vec![])
+}
+
+fn describe(kind: ListKind, list: &NameList, emoji: &HashMap<String, String>) -> String {
+    let title = match kind {
+        ListKind::Categories => "categorie",
+        ListKind::Wallets => "portafogli",
+        ListKind::IncomeCategories => "categorie dei guadagni",
+    };
+    let mut text = format!("{}:", title);
+    for (i, name) in list.active.iter().enumerate() {
+        match emoji.get(name) {
+            Some(emoji) => text.push_str(&format!("\n{}. {} {}", i + 1, emoji, name)),
+            None => text.push_str(&format!("\n{}. {}", i + 1, name)),
+        }
+    }
+    if !list.archived.is_empty() {
+        text.push_str(&format!("\nin archivio: {}", list.archived.join(", ")));
+    }
+    text
+}
+
+fn usage(kind: ListKind) -> String {
+    let command = match kind {
+        ListKind::Categories => "/categories",
+        ListKind::Wallets => "/wallets",
+        ListKind::IncomeCategories => "/incomecategories",
+    };
+    [
+        "aggiungi <nome>",
+        "rinomina <nome> > <nuovo nome>",
+        "sposta <nome> <posizione>",
+        "archivia <nome>",
+        "ripristina <nome>",
+        "emoji <nome> <emoji>",
+    ]
+    .map(|change| format!("{} {}", command, change))
+    .join("\n")
+}
+
+pub async fn categories(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    args: String,
+) -> HandlerResult {
+    manage(bot, dialogue, ledger, settings, ListKind::Categories, args).await
+}
+
+pub async fn wallets(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    args: String,
+) -> HandlerResult {
+    manage(bot, dialogue, ledger, settings, ListKind::Wallets, args).await
+}
+
+pub async fn income_categories(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    args: String,
+) -> HandlerResult {
+    manage(
+        bot,
+        dialogue,
+        ledger,
+        settings,
+        ListKind::IncomeCategories,
+        args,
+    )
+    .await
+}
+
+/// Drops the cached lists, for changes made in the sheet, and reads them
+/// again.
+pub async fn refresh(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+) -> HandlerResult {
+    let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? else {
+        return Ok(());
+    };
+    let chat_id = dialogue.chat_id();
+
+    ledger.refresh(&sheet_data.sheet_id).await;
+    let lists = tokio::try_join!(
+        ledger.categories(&sheet_data.sheet_id),
+        ledger.wallets(&sheet_data.sheet_id),
+        ledger.income_categories(&sheet_data.sheet_id),
+    );
+    match lists {
+        Ok((categories, wallets, income_categories)) => {
+            let text = format!(
+                "aggiornato: {} categorie, {} portafogli, {} categorie dei guadagni",
+                categories.len(),
+                wallets.len(),
+                income_categories.len()
+            );
+            bot.send_message(chat_id, text).await?;
+        }
+        Err(error) => {
+            report_error(&bot, chat_id, "non riesco a leggere le liste", &error).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Shows a list without arguments, otherwise applies the change they ask
+/// for and saves the list back to the ledger.
+async fn manage(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    kind: ListKind,
+    args: String,
+) -> HandlerResult {
+    let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? else {
+        return Ok(());
+    };
+    let chat_id = dialogue.chat_id();
+
+    let change = if args.trim().is_empty() {
+        None
+    } else {
+        match parse_change(&args) {
+            Some(change) => Some(change),
+            None => {
+                bot.send_message(chat_id, usage(kind)).await?;
+                return Ok(());
+            }
+        }
+    };
+
+    let mut list = match ledger.list(&sheet_data.sheet_id, kind).await {
+        Ok(list) => list,
+        Err(error) => {
+            return report_error(&bot, chat_id, "non riesco a leggere la lista", &error).await;
+        }
+    };
+    let Some(change) = change else {
+        let emoji = settings.get(chat_id).emoji;
+        let text = format!("{}\n\n{}", describe(kind, &list, &emoji), usage(kind));
+        bot.send_message(chat_id, text).await?;
+        return Ok(());
+    };
+
+    if let Change::Emoji(name, emoji) = change {
+        let Some(name) = find(&list, &name) else {
+            bot.send_message(chat_id, format!("{} non c'è", name))
+                .await?;
+            return Ok(());
+        };
+        settings.update(chat_id, |chat| match emoji {
+            Some(emoji) => {
+                chat.emoji.insert(name, emoji);
+            }
+            None => {
+                chat.emoji.remove(&name);
+            }
+        })?;
+        let emoji = settings.get(chat_id).emoji;
+        bot.send_message(chat_id, describe(kind, &list, &emoji))
+            .await?;
+        return Ok(());
+    }
+
+    let renamed = match apply(kind, &mut list, change) {
+        Ok(renamed) => renamed,
+        Err(reason) => {
+            bot.send_message(chat_id, reason).await?;
+            return Ok(());
+        }
+    };
+    if let Err(error) = ledger.save_list(&sheet_data.sheet_id, kind, &list).await {
+        return report_error(&bot, chat_id, "salvataggio non riuscito", &error).await;
+    }
+    if !renamed.is_empty() {
+        settings.update(chat_id, |chat| {
+            for (name, new_name) in &renamed {
+                chat.rename(kind, name, new_name);
+            }
+        })?;
+    }
+    let emoji = settings.get(chat_id).emoji;
+    bot.send_message(chat_id, describe(kind, &list, &emoji))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(active: &[&str], archived: &[&str]) -> NameList {
+        NameList {
+            active: active.iter().map(|name| name.to_string()).collect(),
+            archived: archived.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn change(args: &str) -> Change {
+        parse_change(args).unwrap()
+    }
+
+    #[test]
+    fn parses_changes() {
+        assert!(matches!(change("aggiungi Pets"), Change::Add(name) if name == "Pets"));
+        assert!(matches!(
+            change("rinomina Food > Groceries"),
+            Change::Rename(name, new_name) if name == "Food" && new_name == "Groceries"
+        ));
+        assert!(matches!(
+            change("sposta Home and garden 2"),
+            Change::Move(name, 2) if name == "Home and garden"
+        ));
+        assert!(matches!(
+            change("emoji Food 🍕"),
+            Change::Emoji(name, Some(emoji)) if name == "Food" && emoji == "🍕"
+        ));
+        assert!(matches!(change("emoji Food"), Change::Emoji(name, None) if name == "Food"));
+        assert!(parse_change("rinomina Food >").is_none());
+        assert!(parse_change("sposta Food").is_none());
+        assert!(parse_change("cancella Food").is_none());
+    }
+
+    #[test]
+    fn adds_moves_and_restores() {
+        let mut names = list(&["Food", "Home"], &["Pets"]);
+        apply(ListKind::Categories, &mut names, change("aggiungi Leisure")).unwrap();
+        apply(ListKind::Categories, &mut names, change("sposta leisure 1")).unwrap();
+        apply(ListKind::Categories, &mut names, change("ripristina Pets")).unwrap();
+        assert_eq!(names, list(&["Leisure", "Food", "Home", "Pets"], &[]));
+
+        assert!(apply(ListKind::Categories, &mut names, change("aggiungi food")).is_err());
+        let long = format!("aggiungi {}", "x".repeat(picker::MAX_NAME_LEN + 1));
+        assert!(apply(ListKind::Categories, &mut names, change(&long)).is_err());
+    }
+
+    #[test]
+    fn renames_a_category_with_its_subcategories() {
+        let mut names = list(&["Food", "Food/Restaurants", "Home"], &[]);
+        let renamed = apply(
+            ListKind::Categories,
+            &mut names,
+            change("rinomina food > Groceries"),
+        )
+        .unwrap();
+        assert_eq!(
+            names,
+            list(&["Groceries", "Groceries/Restaurants", "Home"], &[])
+        );
+        assert_eq!(
+            renamed,
+            vec![
+                ("Food".to_string(), "Groceries".to_string()),
+                (
+                    "Food/Restaurants".to_string(),
+                    "Groceries/Restaurants".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn renames_a_category_listed_only_through_subcategories() {
+        let mut names = list(&["Food/Restaurants", "Food/Groceries", "Home"], &[]);
+        let renamed = apply(
+            ListKind::Categories,
+            &mut names,
+            change("rinomina Food > Meals"),
+        )
+        .unwrap();
+        assert_eq!(
+            names,
+            list(&["Meals/Restaurants", "Meals/Groceries", "Home"], &[])
+        );
+        assert!(renamed.contains(&("Food".to_string(), "Meals".to_string())));
+    }
+
+    #[test]
+    fn wallets_have_no_subcategories() {
+        let mut names = list(&["Bank/Savings", "Bank"], &[]);
+        apply(
+            ListKind::Wallets,
+            &mut names,
+            change("rinomina Bank > Account"),
+        )
+        .unwrap();
+        assert_eq!(names, list(&["Bank/Savings", "Account"], &[]));
+    }
+
+    #[test]
+    fn archives_a_category_with_its_subcategories() {
+        let mut names = list(&["Food", "Home", "Food/Restaurants"], &[]);
+        apply(ListKind::Categories, &mut names, change("archivia Food")).unwrap();
+        assert_eq!(names, list(&["Home"], &["Food/Restaurants", "Food"]));
+
+        // the last category can't go, nor a category with all that's left
+        assert!(apply(ListKind::Categories, &mut names, change("archivia Home")).is_err());
+        let mut names = list(&["Food", "Food/Restaurants"], &[]);
+        assert!(apply(ListKind::Categories, &mut names, change("archivia Food")).is_err());
+        assert!(apply(ListKind::Categories, &mut names, change("archivia Pets")).is_err());
+    }
+}
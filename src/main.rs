@@ -1,26 +1,81 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 use teloxide::{
-    dispatching::{dialogue, dialogue::InMemStorage, UpdateHandler},
+    dispatching::{
+        dialogue,
+        dialogue::{
+            serializer::{Json, Serializer},
+            SqliteStorage,
+        },
+        UpdateHandler,
+    },
     prelude::*,
     utils::command::BotCommands,
 };
 
+mod calc;
+mod calendar;
+mod categories;
+mod confirm;
+mod dates;
 mod earn_functions;
+mod layout;
+mod ledger;
+mod list_functions;
+mod money;
 mod pay_functions;
+mod picker;
+mod quick_entry;
+mod recent_functions;
+mod settings;
+mod setup_functions;
 mod sheet_api;
+mod sqlite_ledger;
 mod structs;
+mod write_queue;
 
-type MainDialogue = Dialogue<MainState, InMemStorage<MainState>>;
+type MainStorage = SqliteStorage<LenientJson>;
+type MainDialogue = Dialogue<MainState, MainStorage>;
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
-#[derive(Clone, Default)]
+/// JSON for the stored dialogues that restarts a dialogue whose state no
+/// longer reads, as after a change to the entry structs, instead of leaving
+/// the chat stuck: teloxide drops every update of a chat whose dialogue
+/// fails to load, /cancel included.
+pub struct LenientJson;
+
+impl<D> Serializer<D> for LenientJson
+where
+    D: Serialize + serde::de::DeserializeOwned + Default,
+{
+    type Error = serde_json::Error;
+
+    fn serialize(&self, val: &D) -> Result<Vec<u8>, Self::Error> {
+        Json.serialize(val)
+    }
+
+    fn deserialize(&self, data: &[u8]) -> Result<D, Self::Error> {
+        Ok(Json
+            .deserialize(data)
+            .unwrap_or_else(|e: serde_json::Error| {
+                log::warn!("stored dialogue not read, starting over: {}", e);
+                D::default()
+            }))
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub enum MainState {
     #[default]
     Start,
     PTitle {
         p_data: Box<structs::PagamentoStruct>,
+        sheet_data: Box<structs::SheetData>,
     },
     PAmount {
         p_data: Box<structs::PagamentoStruct>,
+        sheet_data: Box<structs::SheetData>,
     },
     PDate {
         p_data: Box<structs::PagamentoStruct>,
@@ -38,16 +93,38 @@ pub enum MainState {
         p_data: Box<structs::PagamentoStruct>,
         sheet_data: Box<structs::SheetData>,
     },
+    PConfirm {
+        p_data: Box<structs::PagamentoStruct>,
+        sheet_data: Box<structs::SheetData>,
+    },
     GTitle {
         g_data: Box<structs::GuadagnoStruct>,
+        sheet_data: Box<structs::SheetData>,
     },
     GAmount {
         g_data: Box<structs::GuadagnoStruct>,
+        sheet_data: Box<structs::SheetData>,
     },
     GDate {
         g_data: Box<structs::GuadagnoStruct>,
         sheet_data: Box<structs::SheetData>,
     },
+    GCategory {
+        g_data: Box<structs::GuadagnoStruct>,
+        sheet_data: Box<structs::SheetData>,
+    },
+    GWallet {
+        g_data: Box<structs::GuadagnoStruct>,
+        sheet_data: Box<structs::SheetData>,
+    },
+    GNotes {
+        g_data: Box<structs::GuadagnoStruct>,
+        sheet_data: Box<structs::SheetData>,
+    },
+    GConfirm {
+        g_data: Box<structs::GuadagnoStruct>,
+        sheet_data: Box<structs::SheetData>,
+    },
     GetLink,
 }
 
@@ -60,8 +137,28 @@ pub enum Command {
     Pagamento,
     #[command(description = "guadagno")]
     Guadagno,
+    #[command(description = "pagamento veloce, es. /p 3.50 caffè #Food @Cash note")]
+    P(String),
+    #[command(description = "guadagno veloce, es. /g 1200 stipendio #Salary @Bank")]
+    G(String),
+    #[command(description = "ultimo pagamento e guadagno, da modificare o eliminare")]
+    Last,
+    #[command(description = "ultimi movimenti, da modificare o eliminare")]
+    Recent,
+    #[command(description = "annulla l'ultimo movimento scritto")]
+    Undo,
+    #[command(description = "categorie: aggiungi, rinomina, sposta, archivia")]
+    Categories(String),
+    #[command(description = "portafogli: aggiungi, rinomina, sposta, archivia")]
+    Wallets(String),
+    #[command(description = "categorie dei guadagni: aggiungi, rinomina, sposta, archivia")]
+    IncomeCategories(String),
+    #[command(description = "rilegge categorie e portafogli dal foglio")]
+    Refresh,
     #[command(description = "spreadsheet link")]
     Link,
+    #[command(description = "prepara un foglio vuoto, es. /setup <link>")]
+    Setup(String),
     #[command(description = "cancella")]
     Cancel,
 }
@@ -73,8 +170,36 @@ async fn main() {
 
     let bot = Bot::from_env();
 
+    // dialogues survive restarts, so a half-finished entry resumes where it was
+    let storage_path =
+        std::env::var("DIALOGUE_DB_PATH").unwrap_or_else(|_| "dialogues.sqlite".to_string());
+    let storage = MainStorage::open(&storage_path, LenientJson)
+        .await
+        .expect("dialogue storage not opened");
+
+    // a single backend (and Sheets hub, whose authenticator refreshes the
+    // token) serves every chat
+    let ledger = ledger::connect()
+        .await
+        .expect("ledger backend not initialized");
+
+    let settings_path =
+        std::env::var("CHAT_SETTINGS_PATH").unwrap_or_else(|_| "chat_settings.json".to_string());
+    let settings = settings::SettingsStore::open(settings_path).expect("chat settings not loaded");
+
+    // entries that couldn't be written are retried in the background
+    let queue_path =
+        std::env::var("WRITE_QUEUE_PATH").unwrap_or_else(|_| "write_queue.json".to_string());
+    let queue = write_queue::WriteQueue::open(queue_path).expect("write queue not loaded");
+    tokio::spawn(write_queue::run(
+        queue.clone(),
+        ledger.clone(),
+        settings.clone(),
+        bot.clone(),
+    ));
+
     Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![InMemStorage::<MainState>::new()])
+        .dependencies(dptree::deps![storage, ledger, settings, queue])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -89,15 +214,34 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
                 .branch(case![Command::Help].endpoint(help))
                 .branch(case![Command::Pagamento].endpoint(pay_functions::pagamento))
                 .branch(case![Command::Guadagno].endpoint(earn_functions::guadagno))
-                .branch(case![Command::Link].endpoint(link)),
+                .branch(case![Command::P(args)].endpoint(pay_functions::pagamento_quick))
+                .branch(case![Command::G(args)].endpoint(earn_functions::guadagno_quick))
+                .branch(case![Command::Last].endpoint(recent_functions::last))
+                .branch(case![Command::Recent].endpoint(recent_functions::recent))
+                .branch(case![Command::Undo].endpoint(recent_functions::undo))
+                .branch(case![Command::Categories(args)].endpoint(list_functions::categories))
+                .branch(case![Command::Wallets(args)].endpoint(list_functions::wallets))
+                .branch(
+                    case![Command::IncomeCategories(args)]
+                        .endpoint(list_functions::income_categories),
+                )
+                .branch(case![Command::Refresh].endpoint(list_functions::refresh))
+                .branch(case![Command::Link].endpoint(link))
+                .branch(case![Command::Setup(link)].endpoint(setup_functions::setup)),
         )
         .branch(case![Command::Cancel].endpoint(cancel))
         .endpoint(no_command);
 
     let message_handler = Update::filter_message()
         .branch(command_handler)
-        .branch(case![MainState::PTitle { p_data }].endpoint(pay_functions::pagamento_title))
-        .branch(case![MainState::PAmount { p_data }].endpoint(pay_functions::pagamento_amount))
+        .branch(
+            case![MainState::PTitle { p_data, sheet_data }]
+                .endpoint(pay_functions::pagamento_title),
+        )
+        .branch(
+            case![MainState::PAmount { p_data, sheet_data }]
+                .endpoint(pay_functions::pagamento_amount),
+        )
         .branch(
             case![MainState::PDate { p_data, sheet_data }].endpoint(pay_functions::pagamento_date),
         )
@@ -105,14 +249,45 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
             case![MainState::PNotes { p_data, sheet_data }]
                 .endpoint(pay_functions::pagamento_notes),
         )
-        .branch(case![MainState::GTitle { g_data }].endpoint(earn_functions::guadagno_title))
-        .branch(case![MainState::GAmount { g_data }].endpoint(earn_functions::guadagno_amount))
+        .branch(
+            case![MainState::PCategory { p_data, sheet_data }]
+                .endpoint(pay_functions::pagamento_retry),
+        )
+        .branch(
+            case![MainState::PWallet { p_data, sheet_data }]
+                .endpoint(pay_functions::pagamento_retry),
+        )
+        .branch(
+            case![MainState::GTitle { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_title),
+        )
+        .branch(
+            case![MainState::GAmount { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_amount),
+        )
         .branch(
             case![MainState::GDate { g_data, sheet_data }].endpoint(earn_functions::guadagno_date),
         )
+        .branch(
+            case![MainState::GNotes { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_notes),
+        )
+        .branch(
+            case![MainState::GCategory { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_retry),
+        )
+        .branch(
+            case![MainState::GWallet { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_retry),
+        )
         .branch(case![MainState::GetLink].endpoint(get_link));
 
     let callback_query_handler = Update::filter_callback_query()
+        .branch(case![MainState::Start].endpoint(recent_functions::entry_action))
+        .branch(
+            case![MainState::PDate { p_data, sheet_data }]
+                .endpoint(pay_functions::pagamento_calendar),
+        )
         .branch(
             case![MainState::PCategory { p_data, sheet_data }]
                 .endpoint(pay_functions::pagamento_category),
@@ -120,9 +295,29 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
         .branch(
             case![MainState::PWallet { p_data, sheet_data }]
                 .endpoint(pay_functions::pagamento_wallet),
+        )
+        .branch(
+            case![MainState::PConfirm { p_data, sheet_data }]
+                .endpoint(pay_functions::pagamento_confirm),
+        )
+        .branch(
+            case![MainState::GDate { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_calendar),
+        )
+        .branch(
+            case![MainState::GCategory { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_category),
+        )
+        .branch(
+            case![MainState::GWallet { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_wallet),
+        )
+        .branch(
+            case![MainState::GConfirm { g_data, sheet_data }]
+                .endpoint(earn_functions::guadagno_confirm),
         );
 
-    dialogue::enter::<Update, InMemStorage<MainState>, MainState, _>()
+    dialogue::enter::<Update, MainStorage, MainState, _>()
         .branch(message_handler)
         .branch(callback_query_handler)
 }
@@ -139,29 +334,101 @@ async fn no_command(bot: Bot, msg: Message) -> HandlerResult {
 }
 
 async fn cancel(bot: Bot, msg: Message, dialogue: MainDialogue) -> HandlerResult {
-    dialogue.exit().await?;
+    dialogue.reset().await?;
     bot.send_message(msg.chat.id, Command::descriptions().to_string())
         .await?;
     Ok(())
 }
 
+/// Sheet the chat writes to, or `None` after asking for a /link when the
+/// backend needs one and the chat hasn't sent it yet.
+pub async fn linked_sheet(
+    bot: &Bot,
+    dialogue: &MainDialogue,
+    settings: &settings::SettingsStore,
+) -> Result<Option<Box<structs::SheetData>>, Box<dyn std::error::Error + Send + Sync>> {
+    // without a link to tell them apart, the entries of each chat are kept
+    // under its id
+    if !ledger::BackendKind::from_env().requires_target() {
+        let target = dialogue.chat_id().to_string();
+        return Ok(Some(Box::new(structs::SheetData::new(target))));
+    }
+
+    let sheet_id = settings
+        .get(dialogue.chat_id())
+        .sheet_id
+        .unwrap_or_default();
+    if sheet_id.is_empty() {
+        bot.send_message(dialogue.chat_id(), "manda link del foglio")
+            .await?;
+        dialogue.update(MainState::GetLink).await?;
+        return Ok(None);
+    }
+    Ok(Some(Box::new(structs::SheetData::new(sheet_id))))
+}
+
+/// Tells the chat that talking to the ledger failed, so nobody assumes the
+/// entry was saved. The dialogue is left as it is for the user to retry.
+pub async fn report_error(
+    bot: &Bot,
+    chat_id: ChatId,
+    what: &str,
+    error: &ledger::LedgerError,
+) -> HandlerResult {
+    log::error!("{}: {}", what, error);
+    bot.send_message(chat_id, format!("⚠️ {}: {}", what, error))
+        .await?;
+    Ok(())
+}
+
 async fn link(bot: Bot, dialogue: MainDialogue, msg: Message) -> HandlerResult {
-    bot.send_message(msg.chat.id, "manda link del file").await?;
+    if !ledger::BackendKind::from_env().requires_target() {
+        bot.send_message(msg.chat.id, "con il database locale non serve nessun link")
+            .await?;
+        return Ok(());
+    }
+    bot.send_message(msg.chat.id, "manda il link o l'id del foglio")
+        .await?;
     dialogue.update(MainState::GetLink).await?;
     Ok(())
 }
 
-async fn get_link(bot: Bot, dialogue: MainDialogue, msg: Message) -> HandlerResult {
-    dialogue.exit().await?;
-    let hub = sheet_api::api_init().await;
+async fn get_link(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: ledger::SharedLedger,
+    settings: Arc<settings::SettingsStore>,
+    msg: Message,
+) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if sheet_api::check_sheet_id(text.to_string(), &hub).await {
-                sheet_api::write_sheet_id(text.to_string(), msg.chat.id);
+            let problems = match ledger.validate_target(text).await {
+                Ok(problems) => problems,
+                Err(error) => {
+                    report_error(
+                        &bot,
+                        msg.chat.id,
+                        "non riesco a controllare il foglio",
+                        &error,
+                    )
+                    .await?;
+                    bot.send_message(msg.chat.id, "manda di nuovo il link per riprovare")
+                        .await?;
+                    return Ok(());
+                }
+            };
+            if problems.is_empty() {
+                let sheet_id = sheet_api::sheet_key(text).map(str::to_string);
+                settings.update(msg.chat.id, |chat| chat.sheet_id = sheet_id)?;
                 bot.send_message(msg.chat.id, "ok").await?;
                 dialogue.exit().await?;
             } else {
-                bot.send_message(msg.chat.id, "manda un link").await?;
+                let text = format!(
+                    "il foglio non va bene:\n- {}\nsistemalo e manda di nuovo il link, \
+                     oppure usa /setup su un foglio vuoto",
+                    problems.join("\n- ")
+                );
+                bot.send_message(msg.chat.id, text).await?;
                 dialogue.update(MainState::GetLink).await?;
             }
         }
@@ -172,3 +439,23 @@ async fn get_link(bot: Bot, dialogue: MainDialogue, msg: Message) -> HandlerResu
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_dialogue_starts_over() {
+        let state: MainState = LenientJson
+            .deserialize(br#"{"PAmount":{"p_data":{"amount":1.5}}}"#)
+            .unwrap();
+        assert!(matches!(state, MainState::Start));
+    }
+
+    #[test]
+    fn readable_dialogue_is_kept() {
+        let data = LenientJson.serialize(&MainState::GetLink).unwrap();
+        let state: MainState = LenientJson.deserialize(&data).unwrap();
+        assert!(matches!(state, MainState::GetLink));
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+pub static INVALID_AMOUNT: &str =
+    "l'importo deve essere un numero o un calcolo, es. 12,50, €12.50 o 45/3";
+
+/// Amount of money kept as a whole number of cents, so it's always exact.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Money {
+    cents: i64,
+}
+
+impl Money {
+    pub fn from_cents(cents: i64) -> Self {
+        Self { cents }
+    }
+
+    pub fn cents(self) -> i64 {
+        self.cents
+    }
+
+    /// Parses an amount as typed in chat: "12.50", "12,50", "€12.50",
+    /// "12,50 €", "1.234,56", "1,234.56" or "1.234.567". Separators group
+    /// thousands only when the first group has 1 to 3 digits and is not 0,
+    /// and every later group has exactly 3; a single separator followed by
+    /// three digits, as in "1.859", could be either and is refused, like
+    /// any amount with more than two decimals.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text: String = text
+            .trim()
+            .trim_start_matches('€')
+            .trim_end_matches('€')
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.as_str()),
+        };
+        if text.is_empty()
+            || !text
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '.' || c == ',')
+        {
+            return None;
+        }
+
+        // the last separator is the decimal one, unless the same separator
+        // comes before it too, as in "1.234.567"
+        let (grouped, decimals) = match text.rfind(['.', ',']) {
+            Some(index) => {
+                let separator = &text[index..index + 1];
+                if text.len() - index - 1 == 3 && text[..index].contains(separator) {
+                    (text, "")
+                } else {
+                    (&text[..index], &text[index + 1..])
+                }
+            }
+            None => (text, ""),
+        };
+        if decimals.len() > 2 || (grouped.is_empty() && decimals.is_empty()) {
+            return None;
+        }
+        let units = thousands(grouped, &text[grouped.len()..])?;
+
+        let units: i64 = if units.is_empty() {
+            0
+        } else {
+            units.parse().ok()?
+        };
+        let decimals: i64 = format!("{:0<2}", decimals).parse().ok()?;
+        let cents = units.checked_mul(100)?.checked_add(decimals)?;
+        Some(Self::from_cents(if negative { -cents } else { cents }))
+    }
+}
+
+/// Digits of the whole part of an amount, checking that any separator in it
+/// groups thousands and differs from the decimal one, which starts `rest`.
+fn thousands(grouped: &str, rest: &str) -> Option<String> {
+    let groups: Vec<&str> = grouped.split(['.', ',']).collect();
+    if let [first, others @ ..] = groups.as_slice() {
+        if !others.is_empty() {
+            let separators: Vec<char> = grouped.matches(['.', ',']).flat_map(str::chars).collect();
+            let decimal = rest.chars().next();
+            if !(1..=3).contains(&first.len())
+                || first.starts_with('0')
+                || others.iter().any(|group| group.len() != 3)
+                || separators
+                    .iter()
+                    .any(|&c| c != separators[0] || Some(c) == decimal)
+            {
+                return None;
+            }
+        }
+    }
+    Some(groups.concat())
+}
+
+impl fmt::Display for Money {
+    /// Writes the amount with a dot and two decimals, as the Sheets API
+    /// expects numbers.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.cents < 0 { "-" } else { "" };
+        let cents = self.cents.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cents(text: &str) -> Option<i64> {
+        Money::parse(text).map(Money::cents)
+    }
+
+    #[test]
+    fn parses_documented_forms() {
+        assert_eq!(cents("12.50"), Some(1250));
+        assert_eq!(cents("12,50"), Some(1250));
+        assert_eq!(cents("€12.50"), Some(1250));
+        assert_eq!(cents("12,50 €"), Some(1250));
+        assert_eq!(cents("1.234,56"), Some(123456));
+        assert_eq!(cents("1,234.56"), Some(123456));
+        assert_eq!(cents("1.234.567"), Some(123456700));
+        assert_eq!(cents("45"), Some(4500));
+        assert_eq!(cents("0,5"), Some(50));
+        assert_eq!(cents("-3.20"), Some(-320));
+    }
+
+    #[test]
+    fn refuses_more_than_two_decimals() {
+        assert_eq!(cents("0.005"), None);
+        assert_eq!(cents("0,125"), None);
+        assert_eq!(cents("1.859"), None);
+    }
+
+    #[test]
+    fn refuses_broken_grouping() {
+        assert_eq!(cents("1.2.3"), None);
+        assert_eq!(cents("0.123.456"), None);
+        assert_eq!(cents("1234.567.890"), None);
+        assert_eq!(cents("1.23.456"), None);
+        assert_eq!(cents("1.234.56"), None);
+        assert_eq!(cents("1,234,56"), None);
+        assert_eq!(cents("1.234,567"), None);
+    }
+
+    #[test]
+    fn refuses_what_is_not_a_number() {
+        assert_eq!(cents(""), None);
+        assert_eq!(cents("€"), None);
+        assert_eq!(cents("."), None);
+        assert_eq!(cents("12a"), None);
+    }
+
+    #[test]
+    fn displays_with_a_dot_and_two_decimals() {
+        assert_eq!(Money::from_cents(123456).to_string(), "1234.56");
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+    }
+}
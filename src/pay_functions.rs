@@ -1,25 +1,253 @@
-use std::num::{ParseFloatError, ParseIntError};
+use std::sync::Arc;
 
+use chrono::Local;
 use teloxide::{
     payloads::SendMessageSetters,
     requests::Requester,
-    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message},
+    types::{CallbackQuery, Message},
     Bot,
 };
 
-use crate::{sheet_api, structs, HandlerResult, MainDialogue, MainState};
+use crate::{
+    calc, calendar, categories, confirm, dates,
+    ledger::{EntryKind, LedgerResult, ListKind, SharedLedger},
+    linked_sheet, money,
+    picker::{self, Picked, Picker},
+    quick_entry, recent_functions, report_error,
+    settings::{ChatSettings, LastWrite, SettingsStore},
+    structs,
+    structs::Field,
+    write_queue::{QueuedEntry, WriteQueue},
+    HandlerResult, MainDialogue, MainState,
+};
 
-pub async fn pagamento(bot: Bot, dialogue: MainDialogue, msg: Message) -> HandlerResult {
-    let sheet_id: String = sheet_api::get_sheet_id(msg.chat.id);
-    dbg!(&sheet_id);
-    if sheet_id == "null" || sheet_id.is_empty() {
-        bot.send_message(msg.chat.id, "manda link del foglio")
-            .await?;
-        dialogue.update(MainState::GetLink).await?;
-    } else {
+static RETRY: &str = "manda un messaggio qualsiasi per riprovare";
+
+/// Buttons to keep just the category, or to choose another one, under its
+/// subcategories.
+static NO_SUBCATEGORY: &str = "none";
+static BACK: &str = "back";
+
+async fn list(
+    ledger: &SharedLedger,
+    sheet_data: &structs::SheetData,
+    kind: ListKind,
+) -> LedgerResult<Vec<String>> {
+    match kind {
+        ListKind::Categories => ledger.categories(&sheet_data.sheet_id).await,
+        ListKind::Wallets => ledger.wallets(&sheet_data.sheet_id).await,
+        ListKind::IncomeCategories => ledger.income_categories(&sheet_data.sheet_id).await,
+    }
+}
+
+/// Keyboard of the top-level categories, the ones the chat uses most first.
+fn category_picker(names: &[String], chat: &ChatSettings) -> Picker {
+    let categories = picker::most_used_first(categories::parents(names), &chat.category_usage);
+    Picker::new(categories)
+        .columns(2)
+        .rows(6)
+        .emoji(chat.emoji.clone())
+}
+
+fn subcategory_picker(names: &[String], category: &str, chat: &ChatSettings) -> Picker {
+    let subcategories =
+        picker::most_used_first(categories::children(names, category), &chat.category_usage);
+    Picker::new(subcategories)
+        .columns(2)
+        .rows(6)
+        .emoji(chat.emoji.clone())
+        .hide_prefix(categories::prefix(category))
+        .extra(format!("solo {}", category), NO_SUBCATEGORY)
+        .extra("« indietro", BACK)
+}
+
+/// Keyboard of the wallets, shared by expenses and incomes.
+pub fn wallet_picker(names: Vec<String>, chat: &ChatSettings) -> Picker {
+    Picker::new(picker::most_used_first(names, &chat.wallet_usage))
+        .columns(3)
+        .rows(3)
+        .emoji(chat.emoji.clone())
+}
+
+pub async fn pagamento(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+) -> HandlerResult {
+    if let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? {
         let p_data = Box::new(structs::PagamentoStruct::new());
-        bot.send_message(msg.chat.id, "titolo pagamento").await?;
-        dialogue.update(MainState::PTitle { p_data }).await?;
+        ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
+    }
+    Ok(())
+}
+
+pub async fn pagamento_quick(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    args: String,
+) -> HandlerResult {
+    let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? else {
+        return Ok(());
+    };
+
+    let entry = quick_entry::parse(&args);
+    let mut p_data = Box::new(structs::PagamentoStruct::new());
+
+    if !entry.title.is_empty() {
+        p_data.title = entry.title;
+        p_data.fill(Field::Title);
+    }
+    if let Some(amount) = entry.amount {
+        p_data.amount = amount;
+        p_data.fill(Field::Amount);
+    }
+    p_data.fill(Field::Date);
+    // a list that can't be read leaves the field to its step, which reports
+    // the failure
+    if let Some(category) = entry.category {
+        let categories = ledger
+            .categories(&sheet_data.sheet_id)
+            .await
+            .unwrap_or_default();
+        match categories::find(&categories, &category) {
+            Some((category, subcategory)) => {
+                p_data.category = category;
+                p_data.subcategory = subcategory;
+                p_data.fill(Field::Category);
+            }
+            None => {
+                bot.send_message(
+                    dialogue.chat_id(),
+                    format!("categoria {} non trovata", category),
+                )
+                .await?;
+            }
+        }
+    }
+    if let Some(wallet) = entry.wallet {
+        let wallets = ledger
+            .wallets(&sheet_data.sheet_id)
+            .await
+            .unwrap_or_default();
+        match quick_entry::find_option(&wallets, &wallet) {
+            Some(wallet) => {
+                p_data.wallet = wallet;
+                p_data.fill(Field::Wallet);
+            }
+            None => {
+                bot.send_message(dialogue.chat_id(), format!("wallet {} non trovato", wallet))
+                    .await?;
+            }
+        }
+    }
+    p_data.notes = entry.notes;
+    p_data.fill(Field::Notes);
+
+    ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
+    Ok(())
+}
+
+/// Asks for the first field of `p_data` still missing, or shows the summary
+/// to confirm once all of them are filled.
+pub async fn ask_next(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    mut p_data: Box<structs::PagamentoStruct>,
+    sheet_data: Box<structs::SheetData>,
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+
+    // fields the ledger has no room for are neither asked nor shown
+    let values = ledger.stored_values(&sheet_data.sheet_id, EntryKind::Expense);
+    *p_data = p_data.stored(values);
+
+    match p_data.missing.first().copied() {
+        Some(Field::Title) => {
+            bot.send_message(chat_id, "titolo pagamento").await?;
+            dialogue
+                .update(MainState::PTitle { p_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Amount) => {
+            bot.send_message(chat_id, "quanto").await?;
+            dialogue
+                .update(MainState::PAmount { p_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Date) => {
+            let today = Local::now().date_naive();
+            bot.send_message(chat_id, "data")
+                .reply_markup(calendar::keyboard(today, today))
+                .await?;
+            dialogue
+                .update(MainState::PDate { p_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Category) => {
+            // a category asked again starts over from the top level
+            p_data.category.clear();
+            p_data.subcategory.clear();
+            let names = match list(&ledger, &sheet_data, ListKind::Categories).await {
+                Ok(names) => names,
+                Err(error) => {
+                    report_error(&bot, chat_id, "non riesco a leggere le categorie", &error)
+                        .await?;
+                    bot.send_message(chat_id, RETRY).await?;
+                    dialogue
+                        .update(MainState::PCategory { p_data, sheet_data })
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let picker = category_picker(&names, &settings.get(chat_id));
+            bot.send_message(chat_id, "categoria")
+                .reply_markup(picker.keyboard(0))
+                .await?;
+            dialogue
+                .update(MainState::PCategory { p_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Wallet) => {
+            let names = match list(&ledger, &sheet_data, ListKind::Wallets).await {
+                Ok(names) => names,
+                Err(error) => {
+                    report_error(&bot, chat_id, "non riesco a leggere i wallet", &error).await?;
+                    bot.send_message(chat_id, RETRY).await?;
+                    dialogue
+                        .update(MainState::PWallet { p_data, sheet_data })
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let picker = wallet_picker(names, &settings.get(chat_id));
+            bot.send_message(chat_id, "wallet")
+                .reply_markup(picker.keyboard(0))
+                .await?;
+            dialogue
+                .update(MainState::PWallet { p_data, sheet_data })
+                .await?;
+        }
+        Some(Field::Notes) => {
+            bot.send_message(chat_id, "note aggiuntive").await?;
+            dialogue
+                .update(MainState::PNotes { p_data, sheet_data })
+                .await?;
+        }
+        None => {
+            bot.send_message(chat_id, p_data.summary())
+                .reply_markup(confirm::keyboard(structs::PagamentoStruct::stored_fields(
+                    values,
+                )))
+                .await?;
+            dialogue
+                .update(MainState::PConfirm { p_data, sheet_data })
+                .await?;
+        }
     }
     Ok(())
 }
@@ -27,14 +255,16 @@ pub async fn pagamento(bot: Bot, dialogue: MainDialogue, msg: Message) -> Handle
 pub async fn pagamento_title(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     msg: Message,
-    mut p_data: Box<structs::PagamentoStruct>,
+    (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            bot.send_message(msg.chat.id, "quanto").await?;
             p_data.title = text.to_string();
-            dialogue.update(MainState::PAmount { p_data }).await?;
+            p_data.fill(Field::Title);
+            ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "manda un testo").await?;
@@ -46,31 +276,24 @@ pub async fn pagamento_title(
 pub async fn pagamento_amount(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     msg: Message,
-    mut p_data: Box<structs::PagamentoStruct>,
+    (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
 ) -> HandlerResult {
-    let hub = sheet_api::api_init().await;
-    let sheet_id: String = sheet_api::get_sheet_id(msg.chat.id);
-    if sheet_id.is_empty() {
-        bot.send_message(msg.chat.id, "manda link del foglio")
-            .await?;
-        dialogue.exit().await?;
-    }
-    let sheet_data = Box::new(structs::SheetData::new(hub, sheet_id));
-    let value: Result<f32, ParseFloatError> = msg.text().unwrap().parse::<f32>();
-
-    match value {
-        Ok(text) => {
-            bot.send_message(msg.chat.id, "data").await?;
-            p_data.amount = text;
-            dialogue
-                .update(MainState::PDate { p_data, sheet_data })
-                .await?;
+    let text = msg.text().unwrap_or_default();
+    match calc::evaluate(text) {
+        Some(amount) => {
+            if calc::is_expression(text) {
+                bot.send_message(msg.chat.id, format!("{} = {}", text.trim(), amount))
+                    .await?;
+            }
+            p_data.amount = amount;
+            p_data.fill(Field::Amount);
+            ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
         }
-        Err(err) => {
-            dbg!(err);
-            bot.send_message(msg.chat.id, "la data deve essere un numero")
-                .await?;
+        None => {
+            bot.send_message(msg.chat.id, money::INVALID_AMOUNT).await?;
         }
     }
     Ok(())
@@ -79,85 +302,126 @@ pub async fn pagamento_amount(
 pub async fn pagamento_date(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     msg: Message,
     (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
 ) -> HandlerResult {
-    let value: Result<u8, ParseIntError> = msg.text().unwrap().parse::<u8>();
-    match value {
-        Ok(text) => {
-            let categories_data =
-                sheet_api::get_categories(&sheet_data.sheet, &sheet_data.sheet_id).await;
-
-            p_data.date = text;
-
-            let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
-            for categories in categories_data.chunks(1) {
-                let row = categories
-                    .iter()
-                    .map(|category| {
-                        InlineKeyboardButton::callback(category.to_owned(), category.to_owned())
-                    })
-                    .collect();
-                keyboard.push(row);
-            }
-
-            let categories_keyboard: InlineKeyboardMarkup = InlineKeyboardMarkup::new(keyboard);
-
-            bot.send_message(msg.chat.id, "Select a product:")
-                .reply_markup(categories_keyboard)
-                .await?;
-
-            dialogue
-                .update(MainState::PCategory { p_data, sheet_data })
-                .await?;
+    let today = Local::now().date_naive();
+    match msg.text().and_then(|text| dates::parse_date(text, today)) {
+        Some(date) => {
+            p_data.date = date;
+            p_data.fill(Field::Date);
+            ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
         }
-        Err(err) => {
-            dbg!(err);
-            bot.send_message(msg.chat.id, "manda un testo").await?;
+        None => {
+            bot.send_message(msg.chat.id, dates::INVALID_DATE).await?;
         }
     }
     Ok(())
 }
+
+pub async fn pagamento_calendar(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    if let Some(date) = calendar::handle_callback(&bot, &q).await? {
+        p_data.date = date;
+        p_data.fill(Field::Date);
+        ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
+    }
+    Ok(())
+}
+
+/// Handles the category keyboard, then the one of its subcategories if it
+/// has any, which stays in the same step with the category already set.
 pub async fn pagamento_category(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
     q: CallbackQuery,
 ) -> HandlerResult {
-    if let Some(category) = &q.data {
-        bot.answer_callback_query(q.id).await?;
-        p_data.category = category.to_string();
-        let wallet_sheet = sheet_api::get_wallets(&sheet_data.sheet, &sheet_data.sheet_id).await;
-        let wallets = wallet_sheet
-            .iter()
-            .map(|wallet| InlineKeyboardButton::callback(wallet, wallet));
-
-        bot.send_message(dialogue.chat_id(), "wallet")
-            .reply_markup(InlineKeyboardMarkup::new([wallets]))
+    let chat_id = dialogue.chat_id();
+    let names = match list(&ledger, &sheet_data, ListKind::Categories).await {
+        Ok(names) => names,
+        Err(error) => {
+            bot.answer_callback_query(q.id).await?;
+            return report_error(&bot, chat_id, "non riesco a leggere le categorie", &error).await;
+        }
+    };
+    let chat = settings.get(chat_id);
+
+    if p_data.category.is_empty() {
+        let picked = category_picker(&names, &chat)
+            .handle_callback(&bot, &q)
+            .await?;
+        let Some(Picked::Name(category)) = picked else {
+            return Ok(());
+        };
+        let picker = subcategory_picker(&names, &category, &chat);
+        p_data.category = category;
+        let values = ledger.stored_values(&sheet_data.sheet_id, EntryKind::Expense);
+        if picker.is_empty() || !structs::PagamentoStruct::stores_subcategory(values) {
+            p_data.fill(Field::Category);
+            return ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await;
+        }
+
+        bot.send_message(chat_id, format!("sottocategoria di {}", p_data.category))
+            .reply_markup(picker.keyboard(0))
             .await?;
         dialogue
-            .update(MainState::PWallet { p_data, sheet_data })
+            .update(MainState::PCategory { p_data, sheet_data })
             .await?;
+        return Ok(());
     }
 
-    Ok(())
+    let picked = subcategory_picker(&names, &p_data.category, &chat)
+        .handle_callback(&bot, &q)
+        .await?;
+    match picked {
+        Some(Picked::Name(name)) => {
+            p_data.subcategory = categories::split(&name).1;
+            p_data.fill(Field::Category);
+        }
+        Some(Picked::Extra(key)) if key == NO_SUBCATEGORY => {
+            p_data.fill(Field::Category);
+        }
+        // back to the top level, which ask_next shows again
+        Some(Picked::Extra(_)) => {}
+        None => return Ok(()),
+    }
+    ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await
 }
 
 pub async fn pagamento_wallet(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
     q: CallbackQuery,
 ) -> HandlerResult {
-    if let Some(wallet) = &q.data {
-        bot.answer_callback_query(q.id).await?;
-        p_data.wallet = wallet.to_string();
-        bot.send_message(dialogue.chat_id(), "note aggiuntive")
-            .await?;
-
-        dialogue
-            .update(MainState::PNotes { p_data, sheet_data })
-            .await?;
+    let chat_id = dialogue.chat_id();
+    let names = match list(&ledger, &sheet_data, ListKind::Wallets).await {
+        Ok(names) => names,
+        Err(error) => {
+            bot.answer_callback_query(q.id).await?;
+            return report_error(&bot, chat_id, "non riesco a leggere i wallet", &error).await;
+        }
+    };
+    let picked = wallet_picker(names, &settings.get(chat_id))
+        .handle_callback(&bot, &q)
+        .await?;
+    if let Some(Picked::Name(wallet)) = picked {
+        p_data.wallet = wallet;
+        p_data.fill(Field::Wallet);
+        ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
     }
 
     Ok(())
@@ -166,16 +430,16 @@ pub async fn pagamento_wallet(
 pub async fn pagamento_notes(
     bot: Bot,
     dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
     msg: Message,
     (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            bot.send_message(msg.chat.id, "finito").await?;
             p_data.notes = text.to_string();
-            dbg!(&p_data);
-            sheet_api::write_pagamento_data(&sheet_data.sheet, &sheet_data.sheet_id, p_data).await;
-            dialogue.exit().await?;
+            p_data.fill(Field::Notes);
+            ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "manda un testo").await?;
@@ -183,3 +447,117 @@ pub async fn pagamento_notes(
     }
     Ok(())
 }
+
+/// Asks again for the current step after reading its options failed.
+pub async fn pagamento_retry(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    (p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
+) -> HandlerResult {
+    ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await
+}
+
+pub async fn pagamento_confirm(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    queue: Arc<WriteQueue>,
+    (mut p_data, sheet_data): (Box<structs::PagamentoStruct>, Box<structs::SheetData>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    let values = ledger.stored_values(&sheet_data.sheet_id, EntryKind::Expense);
+    match confirm::handle_callback(&bot, &q).await? {
+        Some(confirm::Action::Save) => {
+            log::debug!("saving {:?}", p_data);
+            let saved = match &p_data.recorded {
+                // other changes may have shifted the row since it was read
+                Some(entry) => match recent_functions::unchanged(
+                    &ledger,
+                    &sheet_data.sheet_id,
+                    entry,
+                    &p_data.recorded_summary,
+                )
+                .await
+                {
+                    Ok(true) => ledger
+                        .update_expense(&sheet_data.sheet_id, entry, &p_data)
+                        .await
+                        .map(|()| None),
+                    Ok(false) => {
+                        bot.send_message(
+                            dialogue.chat_id(),
+                            "il movimento è cambiato nel frattempo, modifica scartata: usa di nuovo /recent",
+                        )
+                        .await?;
+                        dialogue.exit().await?;
+                        return Ok(());
+                    }
+                    Err(error) => Err(error),
+                },
+                None => ledger
+                    .write_expense(&sheet_data.sheet_id, &p_data)
+                    .await
+                    .map(Some),
+            };
+            match saved {
+                Ok(written) => {
+                    if let Some(entry) = written {
+                        settings.update(dialogue.chat_id(), |chat| {
+                            let summary = p_data.stored(values).summary();
+                            chat.last_write = Some(LastWrite::new(entry, summary));
+                            chat.record_use(&p_data.category, &p_data.subcategory, &p_data.wallet);
+                        })?;
+                    }
+                    let text = match p_data.recorded {
+                        Some(_) => "aggiornato",
+                        None => "finito",
+                    };
+                    bot.send_message(dialogue.chat_id(), text).await?;
+                    dialogue.exit().await?;
+                }
+                // a new entry is kept to be written once the ledger is back
+                Err(error) if error.is_transient() && p_data.recorded.is_none() => {
+                    log::warn!("pagamento queued: {}", error);
+                    queue.push(
+                        dialogue.chat_id(),
+                        &sheet_data.sheet_id,
+                        QueuedEntry::Expense(*p_data),
+                    )?;
+                    bot.send_message(
+                        dialogue.chat_id(),
+                        "⏳ il foglio non risponde, il pagamento è in coda e verrà salvato appena possibile",
+                    )
+                    .await?;
+                    dialogue.exit().await?;
+                }
+                Err(error) => {
+                    report_error(&bot, dialogue.chat_id(), "salvataggio non riuscito", &error)
+                        .await?;
+                    // the press removed the buttons, so the summary comes back to retry
+                    bot.send_message(dialogue.chat_id(), p_data.summary())
+                        .reply_markup(confirm::keyboard(structs::PagamentoStruct::stored_fields(
+                            values,
+                        )))
+                        .await?;
+                }
+            }
+        }
+        Some(confirm::Action::Discard) => {
+            let text = match p_data.recorded {
+                Some(_) => "modifica scartata",
+                None => "pagamento scartato",
+            };
+            bot.send_message(dialogue.chat_id(), text).await?;
+            dialogue.exit().await?;
+        }
+        Some(confirm::Action::Edit(field)) => {
+            p_data.missing = vec![field];
+            ask_next(bot, dialogue, ledger, settings, p_data, sheet_data).await?;
+        }
+        None => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use teloxide::{
+    payloads::EditMessageReplyMarkupSetters,
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot, RequestError,
+};
+
+static PREFIX: &str = "pick:";
+
+/// Longest name a button can carry, since Telegram caps callback data at
+/// 64 bytes.
+pub const MAX_NAME_LEN: usize = 64 - 10;
+
+enum PickerAction {
+    Pick(String),
+    Extra(String),
+    Page(usize),
+    Ignore,
+}
+
+/// What was pressed on a [`Picker::keyboard`].
+pub enum Picked {
+    Name(String),
+    /// One of the buttons added with [`Picker::extra`], by its key.
+    Extra(String),
+}
+
+fn button(text: impl Into<String>, action: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(text, format!("{}{}", PREFIX, action))
+}
+
+fn parse_action(data: &str) -> Option<PickerAction> {
+    let action = data.strip_prefix(PREFIX)?;
+    if action == "ignore" {
+        return Some(PickerAction::Ignore);
+    }
+    let (kind, value) = action.split_once(':')?;
+    match kind {
+        "name" => Some(PickerAction::Pick(value.to_string())),
+        "extra" => Some(PickerAction::Extra(value.to_string())),
+        "page" => value.parse().ok().map(PickerAction::Page),
+        _ => None,
+    }
+}
+
+/// Sorts `names` by how often they were picked, keeping the order of the
+/// list among those used as often.
+pub fn most_used_first(mut names: Vec<String>, usage: &HashMap<String, u32>) -> Vec<String> {
+    names.sort_by_key(|name| Reverse(usage.get(name).copied().unwrap_or_default()));
+    names
+}
+
+/// Keyboard to pick one of a list of names, laid out in `columns` and split
+/// in pages of `rows` rows.
+pub struct Picker {
+    options: Vec<String>,
+    emoji: HashMap<String, String>,
+    hidden_prefix: String,
+    extras: Vec<(String, String)>,
+    columns: usize,
+    rows: usize,
+}
+
+impl Picker {
+    /// Names too long for a button, typed straight into the sheet, are left
+    /// out: Telegram would refuse the whole keyboard.
+    pub fn new(options: Vec<String>) -> Self {
+        let (options, too_long): (Vec<_>, Vec<_>) = options
+            .into_iter()
+            .partition(|name| name.len() <= MAX_NAME_LEN);
+        for name in too_long {
+            log::warn!(
+                "{} left off the keyboard, longer than {} bytes",
+                name,
+                MAX_NAME_LEN
+            );
+        }
+        Self {
+            options,
+            emoji: HashMap::new(),
+            hidden_prefix: String::new(),
+            extras: vec![],
+            columns: 2,
+            rows: 5,
+        }
+    }
+
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    pub fn rows(mut self, rows: usize) -> Self {
+        self.rows = rows.max(1);
+        self
+    }
+
+    /// Emoji shown before the names that have one.
+    pub fn emoji(mut self, emoji: HashMap<String, String>) -> Self {
+        self.emoji = emoji;
+        self
+    }
+
+    /// Start of the names left out of the buttons, like the category before
+    /// its subcategories.
+    pub fn hide_prefix(mut self, prefix: String) -> Self {
+        self.hidden_prefix = prefix;
+        self
+    }
+
+    /// Adds a button below the names, reported by `key` when pressed.
+    pub fn extra(mut self, text: impl Into<String>, key: &str) -> Self {
+        self.extras.push((text.into(), key.to_string()));
+        self
+    }
+
+    fn label(&self, name: &str) -> String {
+        let text = name.strip_prefix(&self.hidden_prefix).unwrap_or(name);
+        match self.emoji.get(name) {
+            Some(emoji) => format!("{} {}", emoji, text),
+            None => text.to_string(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    fn pages(&self) -> usize {
+        self.options.len().div_ceil(self.columns * self.rows).max(1)
+    }
+
+    /// Buttons of page `page`, counting from 0, with arrows to the other
+    /// pages when there are more.
+    pub fn keyboard(&self, page: usize) -> InlineKeyboardMarkup {
+        let pages = self.pages();
+        let page = page.min(pages - 1);
+        let page_size = self.columns * self.rows;
+
+        let mut rows: Vec<Vec<InlineKeyboardButton>> = self
+            .options
+            .iter()
+            .skip(page * page_size)
+            .take(page_size)
+            .collect::<Vec<_>>()
+            .chunks(self.columns)
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| button(self.label(name), &format!("name:{}", name)))
+                    .collect()
+            })
+            .collect();
+
+        if pages > 1 {
+            let arrow = |text: &str, target: Option<usize>| match target {
+                Some(target) => button(text, &format!("page:{}", target)),
+                None => button(" ", "ignore"),
+            };
+            rows.push(vec![
+                arrow("«", page.checked_sub(1)),
+                button(format!("{}/{}", page + 1, pages), "ignore"),
+                arrow("»", Some(page + 1).filter(|&next| next < pages)),
+            ]);
+        }
+        if !self.extras.is_empty() {
+            rows.push(
+                self.extras
+                    .iter()
+                    .map(|(text, key)| button(text.as_str(), &format!("extra:{}", key)))
+                    .collect(),
+            );
+        }
+
+        InlineKeyboardMarkup::new(rows)
+    }
+
+    /// Handles a press on a [`Picker::keyboard`]: turns pages in place and
+    /// returns the name or extra button once one is pressed.
+    pub async fn handle_callback(
+        &self,
+        bot: &Bot,
+        q: &CallbackQuery,
+    ) -> Result<Option<Picked>, RequestError> {
+        bot.answer_callback_query(q.id.clone()).await?;
+
+        let action = q.data.as_deref().and_then(parse_action);
+        match (action, &q.message) {
+            // buttons left from before the list changed are ignored
+            (Some(PickerAction::Pick(name)), _) if !self.options.contains(&name) => Ok(None),
+            (Some(PickerAction::Pick(name)), Some(message)) => {
+                bot.edit_message_text(message.chat.id, message.id, self.label(&name))
+                    .await?;
+                Ok(Some(Picked::Name(name)))
+            }
+            (Some(PickerAction::Pick(name)), None) => Ok(Some(Picked::Name(name))),
+            (Some(PickerAction::Extra(key)), message) => {
+                if let Some(message) = message {
+                    bot.edit_message_reply_markup(message.chat.id, message.id)
+                        .await?;
+                }
+                Ok(Some(Picked::Extra(key)))
+            }
+            (Some(PickerAction::Page(page)), Some(message)) => {
+                bot.edit_message_reply_markup(message.chat.id, message.id)
+                    .reply_markup(self.keyboard(page))
+                    .await?;
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_names_too_long_for_a_button() {
+        let long = "x".repeat(MAX_NAME_LEN + 1);
+        let picker = Picker::new(vec!["Food".to_string(), long]);
+        assert_eq!(picker.options, ["Food"]);
+        assert!(Picker::new(vec!["x".repeat(MAX_NAME_LEN + 1)]).is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{calc, money::Money};
+
+/// Fields read from a one-line entry like `/p 3.50 caffè #Food @Cash note`:
+/// the amount first, then the title, `#category`, `@wallet` and whatever
+/// follows them as notes. Anything left out is asked afterwards.
+#[derive(Default)]
+pub struct QuickEntry {
+    pub amount: Option<Money>,
+    pub title: String,
+    pub category: Option<String>,
+    pub wallet: Option<String>,
+    pub notes: String,
+}
+
+pub fn parse(text: &str) -> QuickEntry {
+    let mut entry = QuickEntry::default();
+    let mut words = text.split_whitespace().peekable();
+
+    if let Some(amount) = words.peek().and_then(|word| calc::evaluate(word)) {
+        entry.amount = Some(amount);
+        words.next();
+    }
+
+    let mut title = vec![];
+    let mut notes = vec![];
+    for word in words {
+        if let Some(category) = word.strip_prefix('#') {
+            entry.category = Some(category.to_string());
+        } else if let Some(wallet) = word.strip_prefix('@') {
+            entry.wallet = Some(wallet.to_string());
+        } else if entry.category.is_some() || entry.wallet.is_some() {
+            notes.push(word);
+        } else {
+            title.push(word);
+        }
+    }
+    entry.title = title.join(" ");
+    entry.notes = notes.join(" ");
+
+    entry
+}
+
+/// Finds `name` among `options` ignoring case, with `_` standing for a space
+/// so that `#Food_Restaurants` matches "Food Restaurants".
+pub fn find_option(options: &[String], name: &str) -> Option<String> {
+    let name = name.replace('_', " ").to_lowercase();
+    options
+        .iter()
+        .find(|option| option.to_lowercase() == name)
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_field() {
+        let entry = parse("3.50 caffè al bar #Food @Cash con Marco");
+        assert_eq!(entry.amount, Money::parse("3.50"));
+        assert_eq!(entry.title, "caffè al bar");
+        assert_eq!(entry.category.as_deref(), Some("Food"));
+        assert_eq!(entry.wallet.as_deref(), Some("Cash"));
+        assert_eq!(entry.notes, "con Marco");
+    }
+
+    #[test]
+    fn leaves_out_what_is_missing() {
+        let entry = parse("stipendio");
+        assert_eq!(entry.amount, None);
+        assert_eq!(entry.title, "stipendio");
+        assert_eq!(entry.category, None);
+        assert_eq!(entry.wallet, None);
+        assert_eq!(entry.notes, "");
+
+        let entry = parse("");
+        assert_eq!(entry.amount, None);
+        assert_eq!(entry.title, "");
+    }
+
+    #[test]
+    fn reads_an_expression_as_amount() {
+        let entry = parse("45/3 pizza");
+        assert_eq!(entry.amount, Money::parse("15"));
+        assert_eq!(entry.title, "pizza");
+    }
+
+    #[test]
+    fn finds_options_ignoring_case_and_underscores() {
+        let options = ["Food Restaurants".to_string(), "Cash".to_string()];
+        assert_eq!(
+            find_option(&options, "food_restaurants").as_deref(),
+            Some("Food Restaurants")
+        );
+        assert_eq!(find_option(&options, "CASH").as_deref(), Some("Cash"));
+        assert_eq!(find_option(&options, "Card"), None);
+    }
+}
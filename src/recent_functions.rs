@@ -0,0 +1,331 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::{
+    earn_functions,
+    ledger::{EntryKind, EntryRef, LedgerResult, SharedLedger},
+    linked_sheet, pay_functions, report_error,
+    settings::SettingsStore,
+    structs,
+    structs::Field,
+    HandlerResult, MainDialogue,
+};
+
+static PREFIX: &str = "entry:";
+
+/// How many entries of each kind /recent lists.
+const RECENT_LIMIT: usize = 5;
+
+/// Minutes after a write during which /undo can still remove it, from
+/// `UNDO_WINDOW_MINUTES` (default 10).
+fn undo_window() -> Duration {
+    let minutes = std::env::var("UNDO_WINDOW_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(10);
+    Duration::minutes(minutes)
+}
+
+/// What a button under a recorded entry asks for.
+enum Action {
+    Edit(Field),
+    Delete,
+}
+
+fn button(text: &str, action: &str, entry: &EntryRef) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(text, format!("{}{}:{}", PREFIX, action, entry.key()))
+}
+
+fn parse_action(data: &str) -> Option<(Action, EntryRef)> {
+    let (action, entry) = data.strip_prefix(PREFIX)?.split_once(':')?;
+    let action = match action {
+        "delete" => Action::Delete,
+        field => Action::Edit(Field::from_key(field)?),
+    };
+    Some((action, EntryRef::from_key(entry)?))
+}
+
+/// Buttons under a recorded entry: one to edit each of `fields`, then delete.
+fn keyboard(entry: &EntryRef, fields: &[Field]) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = fields
+        .chunks(3)
+        .map(|fields| {
+            fields
+                .iter()
+                .map(|field| button(&format!("✏️ {}", field.label()), field.key(), entry))
+                .collect()
+        })
+        .collect();
+    rows.push(vec![button("🗑 elimina", "delete", entry)]);
+
+    InlineKeyboardMarkup::new(rows)
+}
+
+pub async fn last(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+) -> HandlerResult {
+    send_recent(bot, dialogue, ledger, settings, 1).await
+}
+
+pub async fn recent(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+) -> HandlerResult {
+    send_recent(bot, dialogue, ledger, settings, RECENT_LIMIT).await
+}
+
+/// Sends the latest `limit` expenses and incomes, one message each with the
+/// buttons to change them.
+async fn send_recent(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    limit: usize,
+) -> HandlerResult {
+    let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? else {
+        return Ok(());
+    };
+    let chat_id = dialogue.chat_id();
+
+    let recent = tokio::try_join!(
+        ledger.recent_expenses(&sheet_data.sheet_id, limit),
+        ledger.recent_incomes(&sheet_data.sheet_id, limit),
+    );
+    let (expenses, incomes) = match recent {
+        Ok(recent) => recent,
+        Err(error) => {
+            return report_error(&bot, chat_id, "non riesco a leggere i movimenti", &error).await;
+        }
+    };
+    if expenses.is_empty() && incomes.is_empty() {
+        bot.send_message(chat_id, "nessun movimento trovato")
+            .await?;
+        return Ok(());
+    }
+
+    let values = ledger.stored_values(&sheet_data.sheet_id, EntryKind::Expense);
+    for (entry, p_data) in expenses {
+        bot.send_message(chat_id, format!("💸 pagamento\n{}", p_data.summary()))
+            .reply_markup(keyboard(
+                &entry,
+                structs::PagamentoStruct::stored_fields(values),
+            ))
+            .await?;
+    }
+    let values = ledger.stored_values(&sheet_data.sheet_id, EntryKind::Income);
+    for (entry, g_data) in incomes {
+        bot.send_message(chat_id, format!("💰 guadagno\n{}", g_data.summary()))
+            .reply_markup(keyboard(
+                &entry,
+                structs::GuadagnoStruct::stored_fields(values),
+            ))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Summary of what the row of `entry` holds now, if anything.
+async fn current_summary(
+    ledger: &SharedLedger,
+    target: &str,
+    entry: &EntryRef,
+) -> LedgerResult<Option<String>> {
+    Ok(match entry.kind {
+        EntryKind::Expense => ledger
+            .expense(target, entry)
+            .await?
+            .map(|p_data| p_data.summary()),
+        EntryKind::Income => ledger
+            .income(target, entry)
+            .await?
+            .map(|g_data| g_data.summary()),
+    })
+}
+
+/// Whether the row of `entry` still holds the entry summarized as `summary`,
+/// as other changes may have shifted the rows since it was read.
+pub async fn unchanged(
+    ledger: &SharedLedger,
+    target: &str,
+    entry: &EntryRef,
+    summary: &str,
+) -> LedgerResult<bool> {
+    let current = current_summary(ledger, target, entry).await?;
+    Ok(current.is_some_and(|current| current.trim_end() == summary.trim_end()))
+}
+
+/// Handles the buttons under an entry listed by /last or /recent. Editing
+/// reuses the entry dialogue, whose summary then updates the row in place.
+pub async fn entry_action(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    q: CallbackQuery,
+) -> HandlerResult {
+    bot.answer_callback_query(q.id.clone()).await?;
+    let Some((action, entry)) = q.data.as_deref().and_then(parse_action) else {
+        return Ok(());
+    };
+    let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? else {
+        return Ok(());
+    };
+    let chat_id = dialogue.chat_id();
+
+    // rows below a changed one may move, so the old buttons can't be trusted
+    if let Some(message) = &q.message {
+        bot.edit_message_reply_markup(message.chat.id, message.id)
+            .await?;
+    }
+
+    // the row must still hold the entry in the message above the button
+    let recorded_summary = q
+        .message
+        .as_ref()
+        .and_then(|message| message.text())
+        .and_then(|text| text.split_once('\n'))
+        .map(|(_, summary)| summary.to_string())
+        .unwrap_or_default();
+    match unchanged(&ledger, &sheet_data.sheet_id, &entry, &recorded_summary).await {
+        Ok(true) => {}
+        Ok(false) => {
+            bot.send_message(
+                chat_id,
+                "il movimento è cambiato nel frattempo, usa di nuovo /recent",
+            )
+            .await?;
+            return Ok(());
+        }
+        Err(error) => {
+            return report_error(&bot, chat_id, "non riesco a leggere il movimento", &error).await;
+        }
+    }
+
+    match (action, entry.kind) {
+        (Action::Delete, _) => match ledger.delete_entry(&sheet_data.sheet_id, &entry).await {
+            Ok(()) => {
+                bot.send_message(chat_id, "eliminato").await?;
+            }
+            Err(error) => {
+                report_error(&bot, chat_id, "eliminazione non riuscita", &error).await?;
+            }
+        },
+        (Action::Edit(field), EntryKind::Expense) => {
+            match ledger.expense(&sheet_data.sheet_id, &entry).await {
+                Ok(Some(mut p_data)) => {
+                    p_data.missing = vec![field];
+                    p_data.recorded = Some(entry);
+                    p_data.recorded_summary = recorded_summary;
+                    pay_functions::ask_next(
+                        bot,
+                        dialogue,
+                        ledger,
+                        settings,
+                        Box::new(p_data),
+                        sheet_data,
+                    )
+                    .await?;
+                }
+                Ok(None) => {
+                    bot.send_message(chat_id, "pagamento non trovato").await?;
+                }
+                Err(error) => {
+                    report_error(&bot, chat_id, "non riesco a leggere il pagamento", &error)
+                        .await?;
+                }
+            }
+        }
+        (Action::Edit(field), EntryKind::Income) => {
+            match ledger.income(&sheet_data.sheet_id, &entry).await {
+                Ok(Some(mut g_data)) => {
+                    g_data.missing = vec![field];
+                    g_data.recorded = Some(entry);
+                    g_data.recorded_summary = recorded_summary;
+                    earn_functions::ask_next(
+                        bot,
+                        dialogue,
+                        ledger,
+                        settings,
+                        Box::new(g_data),
+                        sheet_data,
+                    )
+                    .await?;
+                }
+                Ok(None) => {
+                    bot.send_message(chat_id, "guadagno non trovato").await?;
+                }
+                Err(error) => {
+                    report_error(&bot, chat_id, "non riesco a leggere il guadagno", &error).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes the last entry written from this chat, as long as it is recent
+/// enough and its row hasn't changed since.
+pub async fn undo(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+) -> HandlerResult {
+    let Some(sheet_data) = linked_sheet(&bot, &dialogue, &settings).await? else {
+        return Ok(());
+    };
+    let chat_id = dialogue.chat_id();
+
+    let Some(last_write) = settings.get(chat_id).last_write else {
+        bot.send_message(chat_id, "niente da annullare").await?;
+        return Ok(());
+    };
+    if Utc::now() - last_write.written_at > undo_window() {
+        bot.send_message(
+            chat_id,
+            "l'ultimo movimento è troppo vecchio per annullarlo, usa /recent",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // rows shift when others are deleted, so check it is still the same entry
+    let entry = last_write.entry;
+    let unchanged =
+        match unchanged(&ledger, &sheet_data.sheet_id, &entry, &last_write.summary).await {
+            Ok(unchanged) => unchanged,
+            Err(error) => {
+                return report_error(&bot, chat_id, "annullamento non riuscito", &error).await;
+            }
+        };
+    if !unchanged {
+        settings.update(chat_id, |chat| chat.last_write = None)?;
+        bot.send_message(
+            chat_id,
+            "l'ultimo movimento è stato modificato nel frattempo, usa /recent",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    if let Err(error) = ledger.delete_entry(&sheet_data.sheet_id, &entry).await {
+        return report_error(&bot, chat_id, "annullamento non riuscito", &error).await;
+    }
+    settings.update(chat_id, |chat| chat.last_write = None)?;
+    bot.send_message(chat_id, format!("annullato:\n{}", last_write.summary))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+
+use crate::categories;
+use crate::ledger::{EntryRef, ListKind};
+
+/// File written by older versions, mapping chat ids to bare sheet ids.
+static LEGACY_USER_DATA: &str = "user_data.json";
+
+/// Preferences of a single chat. New fields need `#[serde(default)]` so
+/// files written by older versions still load.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChatSettings {
+    #[serde(default)]
+    pub sheet_id: Option<String>,
+    /// Last entry written from the chat, which /undo can remove.
+    #[serde(default)]
+    pub last_write: Option<LastWrite>,
+    /// Expenses written with each category, to offer the most used first.
+    #[serde(default)]
+    pub category_usage: HashMap<String, u32>,
+    #[serde(default)]
+    pub wallet_usage: HashMap<String, u32>,
+    /// Incomes written with each income category.
+    #[serde(default)]
+    pub income_category_usage: HashMap<String, u32>,
+    /// Emoji shown next to a category or wallet on the keyboards.
+    #[serde(default)]
+    pub emoji: HashMap<String, String>,
+}
+
+impl ChatSettings {
+    /// Counts the category and wallet of an expense just written.
+    pub fn record_use(&mut self, category: &str, subcategory: &str, wallet: &str) {
+        *self.category_usage.entry(category.to_string()).or_default() += 1;
+        if !subcategory.is_empty() {
+            let entry = categories::join(category, subcategory);
+            *self.category_usage.entry(entry).or_default() += 1;
+        }
+        *self.wallet_usage.entry(wallet.to_string()).or_default() += 1;
+    }
+
+    /// Counts the category and wallet of an income just written.
+    pub fn record_income_use(&mut self, category: &str, wallet: &str) {
+        if !category.is_empty() {
+            *self
+                .income_category_usage
+                .entry(category.to_string())
+                .or_default() += 1;
+        }
+        *self.wallet_usage.entry(wallet.to_string()).or_default() += 1;
+    }
+
+    /// Carries the usage and emoji of a category or wallet over to its new
+    /// name.
+    pub fn rename(&mut self, kind: ListKind, name: &str, new_name: &str) {
+        let usage = match kind {
+            ListKind::Categories => &mut self.category_usage,
+            ListKind::Wallets => &mut self.wallet_usage,
+            ListKind::IncomeCategories => &mut self.income_category_usage,
+        };
+        if let Some(count) = usage.remove(name) {
+            usage.insert(new_name.to_string(), count);
+        }
+        if let Some(emoji) = self.emoji.remove(name) {
+            self.emoji.insert(new_name.to_string(), emoji);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LastWrite {
+    pub entry: EntryRef,
+    /// Summary of the entry as written, to tell whether the row still holds
+    /// it when undoing.
+    pub summary: String,
+    pub written_at: DateTime<Utc>,
+}
+
+impl LastWrite {
+    pub fn new(entry: EntryRef, summary: String) -> Self {
+        Self {
+            entry,
+            summary,
+            written_at: Utc::now(),
+        }
+    }
+}
+
+/// Per-chat settings kept in a JSON file. Every change rewrites the file
+/// through a temporary copy, so a crash never leaves it half written, and
+/// the lock serializes chats saving at the same time.
+pub struct SettingsStore {
+    path: PathBuf,
+    chats: Mutex<HashMap<String, ChatSettings>>,
+}
+
+impl SettingsStore {
+    /// Loads the store at `path`, creating it (and importing the legacy
+    /// `user_data.json` if present) on first run.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Arc<Self>> {
+        let path = path.into();
+        let store = if path.exists() {
+            let chats = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            Self {
+                path,
+                chats: Mutex::new(chats),
+            }
+        } else {
+            let store = Self {
+                path,
+                chats: Mutex::new(import_legacy(Path::new(LEGACY_USER_DATA))),
+            };
+            store.save(&store.chats.lock().unwrap())?;
+            store
+        };
+        Ok(Arc::new(store))
+    }
+
+    pub fn get(&self, chat_id: ChatId) -> ChatSettings {
+        let chats = self.chats.lock().unwrap();
+        chats.get(&chat_id.to_string()).cloned().unwrap_or_default()
+    }
+
+    /// Applies `change` to the settings of `chat_id` and persists the store.
+    pub fn update(
+        &self,
+        chat_id: ChatId,
+        change: impl FnOnce(&mut ChatSettings),
+    ) -> io::Result<()> {
+        let mut chats = self.chats.lock().unwrap();
+        change(chats.entry(chat_id.to_string()).or_default());
+        self.save(&chats)
+    }
+
+    fn save(&self, chats: &HashMap<String, ChatSettings>) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(chats)?)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+fn import_legacy(path: &Path) -> HashMap<String, ChatSettings> {
+    let Ok(file_data) = fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    let Ok(legacy) = serde_json::from_str::<HashMap<String, serde_json::Value>>(&file_data) else {
+        log::warn!("{} is not valid json, not imported", path.display());
+        return HashMap::new();
+    };
+
+    legacy
+        .into_iter()
+        .filter_map(|(chat_id, sheet_id)| {
+            let sheet_id = sheet_id.as_str()?.to_string();
+            Some((
+                chat_id,
+                ChatSettings {
+                    sheet_id: Some(sheet_id),
+                    ..Default::default()
+                },
+            ))
+        })
+        .collect()
+}
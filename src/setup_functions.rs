@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use teloxide::{requests::Requester, Bot};
+
+use crate::{
+    ledger::{BackendKind, SharedLedger},
+    report_error,
+    settings::SettingsStore,
+    sheet_api, HandlerResult, MainDialogue,
+};
+
+/// Prepares the spreadsheet sent with /setup <link>, or the one already
+/// linked, and links it to the chat. Without either it asks for a link.
+pub async fn setup(
+    bot: Bot,
+    dialogue: MainDialogue,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    link: String,
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+    if !BackendKind::from_env().requires_target() {
+        bot.send_message(chat_id, "con il database locale non serve nessun foglio")
+            .await?;
+        return Ok(());
+    }
+
+    // the bot can't share a sheet of its own, so it only prepares one the
+    // user already owns
+    let link = link.trim();
+    let target = if link.is_empty() {
+        settings.get(chat_id).sheet_id
+    } else {
+        sheet_api::sheet_key(link).map(str::to_string)
+    };
+    let Some(sheet_id) = target else {
+        bot.send_message(
+            chat_id,
+            "manda il link di un foglio vuoto condiviso (come editor) con l'account del bot, \
+             es. /setup <link del foglio>",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    bot.send_message(chat_id, "preparo il foglio…").await?;
+    if let Err(error) = ledger.set_up(&sheet_id).await {
+        return report_error(
+            &bot,
+            chat_id,
+            "preparazione del foglio non riuscita",
+            &error,
+        )
+        .await;
+    }
+    settings.update(chat_id, |chat| chat.sheet_id = Some(sheet_id.clone()))?;
+
+    let text = format!(
+        "foglio pronto: https://docs.google.com/spreadsheets/d/{}",
+        sheet_id
+    );
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
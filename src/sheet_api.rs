@@ -1,27 +1,24 @@
-use chrono::Datelike;
-use chrono::Local;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{Datelike, Local, Months, NaiveDate};
+use google_sheets4::api::{
+    AddSheetRequest, BatchUpdateSpreadsheetRequest, BatchUpdateValuesRequest, DeleteRangeRequest,
+    DeleteSheetRequest, DuplicateSheetRequest, GridRange, Request, SheetProperties,
+    UpdateSheetPropertiesRequest, ValueRange,
+};
 use google_sheets4::oauth2;
 use google_sheets4::Sheets;
-use google_sheets4::{api::ValueRange, hyper, hyper_rustls, Error};
-use std::fs;
-use teloxide::types::ChatId;
-
-use crate::structs;
-
-static MONTHS: [&str; 12] = [
-    "January",
-    "February",
-    "March",
-    "April",
-    "May",
-    "June",
-    "July",
-    "August",
-    "September",
-    "October",
-    "November",
-    "December",
-];
+use google_sheets4::{hyper, hyper_rustls, Error};
+
+use crate::layout::{column_letters, quote_tab, range_tab, Columns, Layout, LayoutConfig};
+use crate::ledger::{
+    EntryKind, EntryRef, LedgerBackend, LedgerError, LedgerResult, ListKind, NameList,
+    DEFAULT_CATEGORIES, DEFAULT_INCOME_CATEGORIES, DEFAULT_WALLETS,
+};
+use crate::{money::Money, structs};
 
 pub async fn api_init() -> Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>> {
     let secret = oauth2::read_service_account_key("service-account.json")
@@ -49,204 +46,1249 @@ pub async fn api_init() -> Sheets<hyper_rustls::HttpsConnector<hyper::client::Ht
     return hub;
 }
 
-async fn get_empty_cell(
+async fn get_list(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-    cell_range: &str,
-) -> u32 {
-    let month_number = Local::now().month0() as usize;
-
-    let range = format!("{}!{}", MONTHS[month_number], cell_range);
-
-    let response = hub
+    range: &str,
+) -> LedgerResult<Vec<String>> {
+    let (_response, values) = hub
         .spreadsheets()
-        .values_get(sheet_id, &range)
+        .values_get(sheet_id, range)
         .value_render_option("UNFORMATTED_VALUE")
         .major_dimension("COLUMNS")
         .doit()
-        .await;
+        .await?;
 
-    let values = match response {
-        Ok((_response, values)) => values,
-        Err(error) => {
-            println!("Error getting values: {}", error);
-            return None.unwrap();
-        }
-    };
+    Ok(values
+        .values
+        .and_then(|columns| columns.into_iter().next())
+        .unwrap_or_default())
+}
 
-    if values.values.is_none() {
-        return 4;
+/// Values replacing the names in `range` with `names`, blanking the cells
+/// left over when the list shrinks, or `None` if there is nothing to write.
+async fn list_values(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    range: &str,
+    names: &[String],
+) -> LedgerResult<Option<ValueRange>> {
+    let old_len = get_list(hub, sheet_id, range).await?.len();
+    let mut cells = names.to_vec();
+    if cells.len() < old_len {
+        cells.resize(old_len, String::new());
+    }
+    if cells.is_empty() {
+        return Ok(None);
     }
+    Ok(Some(values(range.to_string(), "COLUMNS", vec![cells])))
+}
 
-    return 4 + values.values.unwrap().get(0).unwrap().len() as u32;
+/// Drops the values that don't fit in `columns`, so a layout written before
+/// a field was added keeps working without it.
+fn fit(mut values_vector: Vec<Vec<String>>, columns: &Columns) -> Vec<Vec<String>> {
+    for values in &mut values_vector {
+        values.truncate(columns.width());
+    }
+    values_vector
 }
 
-pub async fn get_pagamenti_empty_cell(
+/// Writes `values_vector` into `row` of `columns`, returning the range
+/// Google Sheets reports as updated.
+async fn write_data(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-) -> u32 {
-    let range: &str = "B4:B1000";
-    return get_empty_cell(hub, sheet_id, range).await;
+    tab: &str,
+    values_vector: Vec<Vec<String>>,
+    columns: &Columns,
+    row: String,
+) -> LedgerResult<String> {
+    let range = format!(
+        "{}!{}{}:{}{}",
+        quote_tab(tab),
+        columns.first,
+        row,
+        columns.last,
+        row
+    );
+
+    let values = ValueRange {
+        values: Some(fit(values_vector, columns)),
+        ..Default::default()
+    };
+
+    let (_response, updated) = hub
+        .spreadsheets()
+        .values_update(values, sheet_id, &range)
+        .value_input_option("USER_ENTERED")
+        .doit()
+        .await?;
+
+    log::info!("written {:?}", updated.updated_range);
+    updated
+        .updated_range
+        .ok_or(LedgerError::UnexpectedResponse("il range scritto"))
 }
 
-pub async fn get_guadagni_empty_cell(
+/// Adds `values_vector` below the last filled row of `columns`, returning
+/// the range Google Sheets reports as written. Sheets picks the row itself,
+/// so two chats writing to the same tab never collide.
+async fn append_data(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-) -> u32 {
-    let range: &str = "I4:I1000";
-    return get_empty_cell(hub, sheet_id, range).await;
+    tab: &str,
+    values_vector: Vec<Vec<String>>,
+    columns: &Columns,
+) -> LedgerResult<String> {
+    let range = format!(
+        "{}!{}{}:{}",
+        quote_tab(tab),
+        columns.first,
+        columns.first_row,
+        columns.last
+    );
+
+    let values = ValueRange {
+        values: Some(fit(values_vector, columns)),
+        ..Default::default()
+    };
+
+    // OVERWRITE fills the first empty row of the table: inserting whole rows
+    // would also push down the other kind's columns
+    let (_response, appended) = hub
+        .spreadsheets()
+        .values_append(values, sheet_id, &range)
+        .value_input_option("USER_ENTERED")
+        .insert_data_option("OVERWRITE")
+        .doit()
+        .await?;
+
+    let updated_range = appended.updates.and_then(|updates| updates.updated_range);
+    log::info!("appended {:?}", updated_range);
+    updated_range.ok_or(LedgerError::UnexpectedResponse("il range scritto"))
 }
 
-async fn get_list(
+/// Row number of the first cell of an A1 range like `March!B12:G12`.
+fn range_row(range: &str) -> LedgerResult<i64> {
+    let cells = range.rsplit('!').next().unwrap_or_default();
+    let row: String = cells
+        .chars()
+        .skip_while(|c| c.is_ascii_alphabetic())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    row.parse()
+        .map_err(|_| LedgerError::UnexpectedResponse("la riga scritta"))
+}
+
+pub async fn write_pagamento_data(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    layout: &Layout,
+    data: &structs::PagamentoStruct,
+) -> LedgerResult<EntryRef> {
+    let tab = layout.month_tab(data.date);
+    let columns = &layout.expenses;
+    let written = append_data(hub, sheet_id, &tab, pagamento_values(data), columns).await?;
+    Ok(EntryRef::new(
+        EntryKind::Expense,
+        data.date,
+        range_row(&written)?,
+    ))
+}
+
+pub async fn write_guadagno_data(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    layout: &Layout,
+    data: &structs::GuadagnoStruct,
+) -> LedgerResult<EntryRef> {
+    let tab = layout.month_tab(data.date);
+    let columns = &layout.incomes;
+    let written = append_data(hub, sheet_id, &tab, guadagno_values(data), columns).await?;
+    Ok(EntryRef::new(
+        EntryKind::Income,
+        data.date,
+        range_row(&written)?,
+    ))
+}
+
+fn pagamento_values(data: &structs::PagamentoStruct) -> Vec<Vec<String>> {
+    vec![vec![
+        data.title.clone(),
+        data.amount.to_string(),
+        data.date.day().to_string(),
+        data.category.clone(),
+        data.wallet.clone(),
+        data.notes.clone(),
+        data.subcategory.clone(),
+    ]]
+}
+
+fn guadagno_values(data: &structs::GuadagnoStruct) -> Vec<Vec<String>> {
+    vec![vec![
+        data.title.clone(),
+        data.amount.to_string(),
+        data.date.day().to_string(),
+        data.category.clone(),
+        data.wallet.clone(),
+        data.notes.clone(),
+    ]]
+}
+
+fn cell(row: &[String], index: usize) -> String {
+    row.get(index).cloned().unwrap_or_default()
+}
+
+/// Rebuilds the date of an entry from the day written in its row.
+fn entry_date(month: NaiveDate, day: &str) -> LedgerResult<NaiveDate> {
+    day.trim()
+        .parse()
+        .ok()
+        .and_then(|day| month.with_day(day))
+        .ok_or_else(|| LedgerError::UnreadableCell(format!("il giorno \"{}\"", day)))
+}
+
+/// Amount of an entry as shown in its row. One the bot can't read is an
+/// error rather than zero, as an edit would write that back.
+fn entry_amount(amount: &str) -> LedgerResult<Money> {
+    Money::parse(amount)
+        .ok_or_else(|| LedgerError::UnreadableCell(format!("l'importo \"{}\"", amount)))
+}
+
+fn pagamento_from_row(month: NaiveDate, row: &[String]) -> LedgerResult<structs::PagamentoStruct> {
+    Ok(structs::PagamentoStruct {
+        title: cell(row, 0),
+        amount: entry_amount(&cell(row, 1))?,
+        date: entry_date(month, &cell(row, 2))?,
+        category: cell(row, 3),
+        subcategory: cell(row, 6),
+        wallet: cell(row, 4),
+        notes: cell(row, 5),
+        missing: vec![],
+        recorded: None,
+        recorded_summary: String::new(),
+    })
+}
+
+fn guadagno_from_row(month: NaiveDate, row: &[String]) -> LedgerResult<structs::GuadagnoStruct> {
+    Ok(structs::GuadagnoStruct {
+        title: cell(row, 0),
+        amount: entry_amount(&cell(row, 1))?,
+        date: entry_date(month, &cell(row, 2))?,
+        category: cell(row, 3),
+        wallet: cell(row, 4),
+        notes: cell(row, 5),
+        missing: vec![],
+        recorded: None,
+        recorded_summary: String::new(),
+    })
+}
+
+/// Pairs the rows listed by /recent with their entries, leaving out the ones
+/// that can't be read back, since they can't be edited either.
+fn readable<T>(
+    rows: Vec<(EntryRef, Vec<String>)>,
+    from_row: fn(NaiveDate, &[String]) -> LedgerResult<T>,
+) -> Vec<(EntryRef, T)> {
+    rows.into_iter()
+        .filter_map(|(entry, row)| match from_row(entry.month, &row) {
+            Ok(data) => Some((entry, data)),
+            Err(error) => {
+                log::warn!("row {} left out of /recent: {}", entry.key(), error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Reads `range` row by row, with the values as shown in the sheet.
+async fn get_rows(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
     range: &str,
-) -> Vec<String> {
-    let response = hub
+) -> LedgerResult<Vec<Vec<String>>> {
+    let (_response, values) = hub
         .spreadsheets()
         .values_get(sheet_id, range)
-        .value_render_option("UNFORMATTED_VALUE")
-        .major_dimension("COLUMNS")
+        .value_render_option("FORMATTED_VALUE")
+        .major_dimension("ROWS")
         .doit()
-        .await;
+        .await?;
+    Ok(values.values.unwrap_or_default())
+}
 
-    let values = match response {
-        Ok((_response, values)) => values,
-        Err(error) => {
-            println!("Error getting values: {}", error);
-            return None.unwrap();
-        }
+/// Latest `limit` rows of the tab of `month` holding entries of `kind`,
+/// newest first.
+async fn get_recent_rows(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    layout: &Layout,
+    kind: EntryKind,
+    month: NaiveDate,
+    limit: usize,
+) -> LedgerResult<Vec<(EntryRef, Vec<String>)>> {
+    let columns = layout.columns(kind);
+    let range = format!(
+        "{}!{}{}:{}",
+        quote_tab(&layout.month_tab(month)),
+        columns.first,
+        columns.first_row,
+        columns.last
+    );
+
+    let rows = get_rows(hub, sheet_id, &range).await?;
+    let first_row = columns.first_row as i64;
+    Ok(rows
+        .into_iter()
+        .enumerate()
+        .filter(|(_, row)| !cell(row, 0).is_empty())
+        .map(|(index, row)| (EntryRef::new(kind, month, first_row + index as i64), row))
+        .rev()
+        .take(limit)
+        .collect())
+}
+
+async fn get_entry_row(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    layout: &Layout,
+    entry: &EntryRef,
+) -> LedgerResult<Option<Vec<String>>> {
+    let columns = layout.columns(entry.kind);
+    let range = format!(
+        "{}!{}{}:{}{}",
+        quote_tab(&layout.month_tab(entry.month)),
+        columns.first,
+        entry.row,
+        columns.last,
+        entry.row
+    );
+
+    let rows = get_rows(hub, sheet_id, &range).await?;
+    Ok(rows
+        .into_iter()
+        .next()
+        .filter(|row| !cell(row, 0).is_empty()))
+}
+
+/// Properties of every tab of the spreadsheet, in order.
+async fn get_tabs(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+) -> LedgerResult<Vec<SheetProperties>> {
+    let (_response, spreadsheet) = hub.spreadsheets().get(sheet_id).doit().await?;
+    Ok(spreadsheet
+        .sheets
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|sheet| sheet.properties)
+        .collect())
+}
+
+fn find_tab_id(tabs: &[SheetProperties], tab: &str) -> LedgerResult<i32> {
+    tabs.iter()
+        .find(|properties| properties.title.as_deref() == Some(tab))
+        .and_then(|properties| properties.sheet_id)
+        .ok_or_else(|| LedgerError::MissingTab(tab.to_string()))
+}
+
+/// Numeric id of the tab called `tab`, needed by structural requests.
+async fn get_tab_id(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    tab: &str,
+) -> LedgerResult<i32> {
+    find_tab_id(&get_tabs(hub, sheet_id).await?, tab)
+}
+
+async fn batch_update(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    requests: Vec<Request>,
+) -> LedgerResult<google_sheets4::api::BatchUpdateSpreadsheetResponse> {
+    let request = BatchUpdateSpreadsheetRequest {
+        requests: Some(requests),
+        ..Default::default()
     };
-    return values.values.unwrap().get(0).unwrap().to_vec();
+    let (_response, reply) = hub
+        .spreadsheets()
+        .batch_update(request, sheet_id)
+        .doit()
+        .await?;
+    Ok(reply)
 }
 
-pub async fn get_categories(
+/// Creates the tab called `tab` as a copy of the layout's template, placed
+/// after the other tabs.
+pub async fn create_month_tab(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-) -> Vec<String> {
-    let range = "Categories!B4:B20";
-    return get_list(hub, sheet_id, range).await;
+    layout: &Layout,
+    tab: &str,
+) -> LedgerResult<()> {
+    let tabs = get_tabs(hub, sheet_id).await?;
+    let template_id = find_tab_id(&tabs, &layout.template_tab)?;
+
+    let duplicate = Request {
+        duplicate_sheet: Some(DuplicateSheetRequest {
+            source_sheet_id: Some(template_id),
+            new_sheet_name: Some(tab.to_string()),
+            insert_sheet_index: Some(tabs.len() as i32),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let reply = batch_update(hub, sheet_id, vec![duplicate]).await?;
+    log::info!("created tab {} in {}", tab, sheet_id);
+
+    // the template is usually hidden, and so is its copy
+    let copy = reply
+        .replies
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .and_then(|reply| reply.duplicate_sheet)
+        .and_then(|duplicate| duplicate.properties);
+    if let Some(SheetProperties {
+        sheet_id: Some(copy_id),
+        hidden: Some(true),
+        ..
+    }) = copy
+    {
+        let show = Request {
+            update_sheet_properties: Some(UpdateSheetPropertiesRequest {
+                properties: Some(SheetProperties {
+                    sheet_id: Some(copy_id),
+                    hidden: Some(false),
+                    ..Default::default()
+                }),
+                fields: Some("hidden".to_string()),
+            }),
+            ..Default::default()
+        };
+        batch_update(hub, sheet_id, vec![show]).await?;
+    }
+    Ok(())
 }
 
-pub async fn get_wallets(
+/// Removes the cells of `entry`, shifting up the entries below it so the
+/// column stays without gaps. The other kind's columns are left alone.
+pub async fn delete_entry_row(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-) -> Vec<String> {
-    let range = "Categories!G4:G20";
-    return get_list(hub, sheet_id, range).await;
+    layout: &Layout,
+    entry: &EntryRef,
+) -> LedgerResult<()> {
+    let tab_id = get_tab_id(hub, sheet_id, &layout.month_tab(entry.month)).await?;
+    let (start_column, end_column) = layout.columns(entry.kind).indices();
+
+    let delete = Request {
+        delete_range: Some(DeleteRangeRequest {
+            range: Some(GridRange {
+                sheet_id: Some(tab_id),
+                start_row_index: Some(entry.row as i32 - 1),
+                end_row_index: Some(entry.row as i32),
+                start_column_index: Some(start_column),
+                end_column_index: Some(end_column),
+            }),
+            shift_dimension: Some("ROWS".to_string()),
+        }),
+        ..Default::default()
+    };
+    batch_update(hub, sheet_id, vec![delete]).await?;
+    Ok(())
 }
 
-async fn write_data(
+pub async fn update_pagamento_data(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-    values_vector: Vec<Vec<String>>,
-    begin: &str,
-    end: &str,
-    row: String,
-) {
-    let month_number = Local::now().month0() as usize;
+    layout: &Layout,
+    entry: &EntryRef,
+    data: &structs::PagamentoStruct,
+) -> LedgerResult<()> {
+    let tab = layout.month_tab(entry.month);
+    if layout.month_tab(data.date) != tab {
+        // the new date belongs to another tab, so the row moves there
+        write_pagamento_data(hub, sheet_id, layout, data).await?;
+        return delete_entry_row(hub, sheet_id, layout, entry).await;
+    }
 
-    let range = format!("{}!{}{}:{}{}", MONTHS[month_number], begin, row, end, row);
+    let row = entry.row.to_string();
+    let columns = &layout.expenses;
+    write_data(hub, sheet_id, &tab, pagamento_values(data), columns, row).await?;
+    Ok(())
+}
 
-    let values = ValueRange {
-        values: Some(values_vector),
+pub async fn update_guadagno_data(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    layout: &Layout,
+    entry: &EntryRef,
+    data: &structs::GuadagnoStruct,
+) -> LedgerResult<()> {
+    let tab = layout.month_tab(entry.month);
+    if layout.month_tab(data.date) != tab {
+        // the new date belongs to another tab, so the row moves there
+        write_guadagno_data(hub, sheet_id, layout, data).await?;
+        return delete_entry_row(hub, sheet_id, layout, entry).await;
+    }
+
+    let row = entry.row.to_string();
+    let columns = &layout.incomes;
+    write_data(hub, sheet_id, &tab, guadagno_values(data), columns, row).await?;
+    Ok(())
+}
+
+/// Id of the spreadsheet in `link`: a bare id or any Google Sheets URL
+/// carrying one, like `.../spreadsheets/d/<id>/edit#gid=0`.
+pub fn sheet_key(link: &str) -> Option<&str> {
+    let link = link.trim();
+    let key = if let Some((_, path)) = link.split_once("/d/") {
+        path
+    } else if let Some((_, query)) = link.split_once('?') {
+        query.split('&').find_map(|param| {
+            param
+                .strip_prefix("key=")
+                .or_else(|| param.strip_prefix("id="))
+        })?
+    } else {
+        link
+    };
+    let key = key.split(['/', '?', '#', '&']).next()?;
+    let valid = key.len() >= 20
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(key)
+}
+
+/// Adds an empty tab after the others.
+async fn create_tab(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    tab: &str,
+) -> LedgerResult<()> {
+    let tabs = get_tabs(hub, sheet_id).await?;
+    batch_update(hub, sheet_id, vec![add_tab(tab, tabs.len())]).await?;
+    log::info!("created tab {} in {}", tab, sheet_id);
+    Ok(())
+}
+
+fn add_tab(title: &str, index: usize) -> Request {
+    Request {
+        add_sheet: Some(AddSheetRequest {
+            properties: Some(SheetProperties {
+                title: Some(title.to_string()),
+                index: Some(index as i32),
+                ..Default::default()
+            }),
+        }),
         ..Default::default()
+    }
+}
+
+fn values(range: String, major_dimension: &str, values: Vec<Vec<String>>) -> ValueRange {
+    ValueRange {
+        range: Some(range),
+        major_dimension: Some(major_dimension.to_string()),
+        values: Some(values),
+    }
+}
+
+/// Headers above the entries of `columns`, if the layout leaves a row for
+/// them.
+fn header_values(tab: &str, columns: &Columns, headers: &[&str]) -> Option<ValueRange> {
+    let row = columns.first_row.checked_sub(1).filter(|&row| row > 0)?;
+    let headers = headers
+        .iter()
+        .take(columns.width())
+        .map(|header| header.to_string())
+        .collect();
+    Some(values(
+        format!(
+            "{}!{}{}:{}{}",
+            quote_tab(tab),
+            columns.first,
+            row,
+            columns.last,
+            row
+        ),
+        "ROWS",
+        vec![headers],
+    ))
+}
+
+/// Monthly totals, right of the entries with a column in between. The
+/// amount is the second field of both kinds of entry.
+fn summary_values(tab: &str, layout: &Layout) -> ValueRange {
+    let amounts = |columns: &Columns| {
+        let amount = column_letters(columns.indices().0 + 1);
+        format!("=SUM({}{}:{})", amount, columns.first_row, amount)
     };
+    let label = layout.expenses.indices().1.max(layout.incomes.indices().1) + 1;
+    let (label, total) = (column_letters(label), column_letters(label + 1));
+    let rows = [
+        ["Expenses".to_string(), amounts(&layout.expenses)],
+        ["Incomes".to_string(), amounts(&layout.incomes)],
+        ["Balance".to_string(), format!("={}2-{}1", total, total)],
+    ];
+    values(
+        format!("{}!{}1:{}3", quote_tab(tab), label, total),
+        "ROWS",
+        rows.into_iter().map(Vec::from).collect(),
+    )
+}
 
-    let response = hub
+/// Whether the tab holds no values at all, like the one a new spreadsheet
+/// comes with.
+async fn is_blank_tab(
+    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    sheet_id: &str,
+    tab: &str,
+) -> LedgerResult<bool> {
+    let (_response, values) = hub
         .spreadsheets()
-        .values_update(values, sheet_id, &range)
-        .value_input_option("USER_ENTERED")
+        .values_get(sheet_id, &quote_tab(tab))
         .doit()
-        .await;
-
-    match response {
-        Err(e) => match e {
-            // The Error enum provides details about what exactly happened.
-            // You can also just use its `Debug`, `Display` or `Error` traits
-            Error::HttpError(_)
-            | Error::Io(_)
-            | Error::MissingAPIKey
-            | Error::MissingToken(_)
-            | Error::Cancelled
-            | Error::UploadSizeLimitExceeded(_, _)
-            | Error::Failure(_)
-            | Error::BadRequest(_)
-            | Error::FieldClash(_)
-            | Error::JsonDecodeError(_, _) => println!("{}", e),
-        },
-        Ok(res) => println!("Success {:?}", res),
-    }
+        .await?;
+    Ok(values.values.unwrap_or_default().is_empty())
 }
 
-pub async fn write_pagamento_data(
+/// Gives a spreadsheet the tabs `layout` expects: the template with headers
+/// and monthly totals, hidden once copied into a tab for each month of
+/// `year`, and the tabs listing the default categories, wallets and income
+/// categories. Tabs already there are left alone, unless one would be
+/// replaced.
+pub async fn set_up_spreadsheet(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-    data: Box<structs::PagamentoStruct>,
-) {
-    let begin = "B";
-    let end = "G";
+    layout: &Layout,
+    year: i32,
+) -> LedgerResult<()> {
+    let existing = get_tabs(hub, sheet_id).await?;
+    // lists can share a tab, in any order
+    let mut seen = HashSet::new();
+    let list_tabs: Vec<String> = [
+        &layout.categories,
+        &layout.wallets,
+        &layout.income_categories,
+    ]
+    .into_iter()
+    .map(|range| range_tab(range))
+    .filter(|tab| seen.insert(tab.clone()))
+    .collect();
+    let month_tabs: Vec<String> = (1..=12)
+        .filter_map(|month| NaiveDate::from_ymd_opt(year, month, 1))
+        .map(|month| layout.month_tab(month))
+        .collect();
 
-    let row = get_pagamenti_empty_cell(hub, sheet_id).await.to_string();
+    let new_tabs = std::iter::once(layout.template_tab.as_str())
+        .chain(list_tabs.iter().map(String::as_str))
+        .chain(month_tabs.iter().map(String::as_str));
+    for tab in new_tabs {
+        if find_tab_id(&existing, tab).is_ok() {
+            return Err(LedgerError::TabExists(tab.to_string()));
+        }
+    }
 
-    let values_vector = vec![vec![
-        data.title,
-        data.amount.to_string(),
-        data.date.to_string(),
-        data.category,
-        data.wallet,
-        data.notes,
-    ]];
+    // a new spreadsheet comes with an empty tab, removed once ours are there
+    let blank = match existing.as_slice() {
+        [only] => match (&only.title, only.sheet_id) {
+            (Some(title), Some(id)) if is_blank_tab(hub, sheet_id, title).await? => Some(id),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    let mut requests = vec![add_tab(&layout.template_tab, existing.len())];
+    for (i, tab) in list_tabs.iter().enumerate() {
+        requests.push(add_tab(tab, existing.len() + 1 + i));
+    }
+    let reply = batch_update(hub, sheet_id, requests).await?;
+    let template_id = reply
+        .replies
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .and_then(|reply| reply.add_sheet)
+        .and_then(|added| added.properties)
+        .and_then(|properties| properties.sheet_id)
+        .ok_or(LedgerError::UnexpectedResponse("l'id della scheda creata"))?;
+
+    let template = &layout.template_tab;
+    let mut data = vec![summary_values(template, layout)];
+    data.extend(header_values(
+        template,
+        &layout.expenses,
+        &[
+            "Title",
+            "Amount",
+            "Day",
+            "Category",
+            "Wallet",
+            "Notes",
+            "Subcategory",
+        ],
+    ));
+    data.extend(header_values(
+        template,
+        &layout.incomes,
+        &["Title", "Amount", "Day", "Category", "Wallet", "Notes"],
+    ));
+    data.push(values(
+        layout.categories.clone(),
+        "COLUMNS",
+        vec![DEFAULT_CATEGORIES.map(str::to_string).to_vec()],
+    ));
+    data.push(values(
+        layout.wallets.clone(),
+        "COLUMNS",
+        vec![DEFAULT_WALLETS.map(str::to_string).to_vec()],
+    ));
+    data.push(values(
+        layout.income_categories.clone(),
+        "COLUMNS",
+        vec![DEFAULT_INCOME_CATEGORIES.map(str::to_string).to_vec()],
+    ));
+    let request = BatchUpdateValuesRequest {
+        data: Some(data),
+        value_input_option: Some("USER_ENTERED".to_string()),
+        ..Default::default()
+    };
+    hub.spreadsheets()
+        .values_batch_update(request, sheet_id)
+        .doit()
+        .await?;
 
-    write_data(hub, sheet_id, values_vector, begin, end, row).await;
+    let first_month = existing.len() + 1 + list_tabs.len();
+    let mut requests: Vec<Request> = month_tabs
+        .iter()
+        .enumerate()
+        .map(|(i, tab)| Request {
+            duplicate_sheet: Some(DuplicateSheetRequest {
+                source_sheet_id: Some(template_id),
+                new_sheet_name: Some(tab.clone()),
+                insert_sheet_index: Some((first_month + i) as i32),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .collect();
+    requests.push(Request {
+        update_sheet_properties: Some(UpdateSheetPropertiesRequest {
+            properties: Some(SheetProperties {
+                sheet_id: Some(template_id),
+                hidden: Some(true),
+                ..Default::default()
+            }),
+            fields: Some("hidden".to_string()),
+        }),
+        ..Default::default()
+    });
+    if let Some(blank) = blank {
+        requests.push(Request {
+            delete_sheet: Some(DeleteSheetRequest {
+                sheet_id: Some(blank),
+            }),
+            ..Default::default()
+        });
+    }
+    batch_update(hub, sheet_id, requests).await?;
+    log::info!("set up spreadsheet {}", sheet_id);
+    Ok(())
 }
 
-pub async fn write_guadagno_data(
+/// Whether Google Sheets refused the request, rather than not answering.
+fn is_refused(error: &LedgerError) -> bool {
+    matches!(error, LedgerError::Sheets(e) if matches!(**e, Error::BadRequest(_) | Error::Failure(_)))
+}
+
+/// What keeps the bot from using `sheet_id` with `layout`, as sentences for
+/// the chat: none if the spreadsheet is usable. Only network and auth
+/// failures are errors.
+pub async fn check_sheet(
     hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     sheet_id: &str,
-    data: Box<structs::GuadagnoStruct>,
-) {
-    let begin = "I";
-    let end = "K";
+    layout: &Layout,
+) -> LedgerResult<Vec<String>> {
+    let tabs = match get_tabs(hub, sheet_id).await {
+        Ok(tabs) => tabs,
+        Err(error) if is_refused(&error) => {
+            return Ok(vec![
+                "il bot non riesce ad aprire il foglio: controlla il link e condividilo \
+                 con l'account del bot"
+                    .to_string(),
+            ]);
+        }
+        Err(error) => return Err(error),
+    };
+    let has_tab = |tab: &str| find_tab_id(&tabs, tab).is_ok();
+    let mut problems = vec![];
 
-    let row = get_guadagni_empty_cell(hub, sheet_id).await.to_string();
+    // without the template, only the months that already have a tab work
+    let month_tab = layout.month_tab(Local::now().date_naive());
+    if !has_tab(&month_tab) && !has_tab(&layout.template_tab) {
+        problems.push(format!(
+            "manca la scheda {} (o {}, da cui il bot crea le schede dei mesi)",
+            month_tab, layout.template_tab
+        ));
+    }
 
-    let values_vector = vec![vec![
-        data.title,
-        data.amount.to_string(),
-        data.date.to_string(),
-    ]];
+    for range in [&layout.categories, &layout.wallets] {
+        let tab = range_tab(range);
+        if !has_tab(&tab) {
+            problems.push(format!("manca la scheda {}", tab));
+            continue;
+        }
+        match get_list(hub, sheet_id, range).await {
+            Ok(list) if list.is_empty() => problems.push(format!("{} è vuoto", range)),
+            Ok(_) => {}
+            Err(error) if is_refused(&error) => {
+                problems.push(format!("{} non è un intervallo valido", range))
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    problems.dedup();
 
-    write_data(hub, sheet_id, values_vector, begin, end, row).await;
+    // renaming a tab to its own name changes nothing, but needs edit rights
+    if let Some(tab) = tabs.first() {
+        let rename = Request {
+            update_sheet_properties: Some(UpdateSheetPropertiesRequest {
+                properties: Some(SheetProperties {
+                    sheet_id: tab.sheet_id,
+                    title: tab.title.clone(),
+                    ..Default::default()
+                }),
+                fields: Some("title".to_string()),
+            }),
+            ..Default::default()
+        };
+        match batch_update(hub, sheet_id, vec![rename]).await {
+            Ok(_) => {}
+            Err(error) if is_refused(&error) => problems.push(
+                "il bot può leggere il foglio ma non modificarlo: condividilo come editor"
+                    .to_string(),
+            ),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(problems)
 }
 
-pub fn write_sheet_id(sheet_id: String, chat_id: ChatId) {
-    let file_data = fs::read_to_string("user_data.json").unwrap();
-    let mut json_data: serde_json::Value = serde_json::from_str(&file_data).unwrap();
+/// Google Sheets implementation of [`LedgerBackend`], writing into the
+/// monthly tabs of the spreadsheet given as target.
+pub struct SheetsLedger {
+    hub: Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    layouts: LayoutConfig,
+    /// Tabs seen in each spreadsheet, so writes don't list them every time.
+    known_tabs: Mutex<HashMap<String, HashSet<String>>>,
+    /// Categories and wallets of each spreadsheet, so keyboards don't wait
+    /// for Google Sheets.
+    lists: Mutex<HashMap<(String, ListKind), CachedList>>,
+}
 
-    let key: &str = sheet_id.split('/').nth(5).unwrap();
+struct CachedList {
+    names: Vec<String>,
+    read_at: Instant,
+}
 
-    json_data[chat_id.to_string()] = serde_json::json!(key);
+impl CachedList {
+    fn new(names: Vec<String>) -> Self {
+        Self {
+            names,
+            read_at: Instant::now(),
+        }
+    }
+}
 
-    fs::write("user_data.json", json_data.to_string()).expect("no buono");
+/// How long categories and wallets are cached, from `LIST_CACHE_SECONDS`
+/// (default 600).
+fn list_ttl() -> Duration {
+    let seconds = std::env::var("LIST_CACHE_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(600);
+    Duration::from_secs(seconds)
 }
 
-pub async fn check_sheet_id(
-    sheet_id: String,
-    hub: &Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
-) -> bool {
-    let key: &str = sheet_id.split('/').nth(5).unwrap();
-    let result = hub.spreadsheets().get(key).doit().await;
-    return result.is_ok();
+impl SheetsLedger {
+    pub fn new(
+        hub: Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+        layouts: LayoutConfig,
+    ) -> Self {
+        Self {
+            hub,
+            layouts,
+            known_tabs: Mutex::new(HashMap::new()),
+            lists: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn cached_list(&self, target: &str, kind: ListKind) -> LedgerResult<Vec<String>> {
+        let key = (target.to_string(), kind);
+        if let Some(cached) = self.lists.lock().unwrap().get(&key) {
+            if cached.read_at.elapsed() < list_ttl() {
+                return Ok(cached.names.clone());
+            }
+        }
+
+        let (range, _) = self.layouts.for_sheet(target).list_ranges(kind);
+        let names = get_list(&self.hub, target, range).await?;
+        self.lists
+            .lock()
+            .unwrap()
+            .insert(key, CachedList::new(names.clone()));
+        Ok(names)
+    }
+
+    /// Latest `limit` rows holding entries of `kind`, newest first. Early in
+    /// the month the current tab may hold fewer, so the previous one follows.
+    async fn recent_rows(
+        &self,
+        target: &str,
+        kind: EntryKind,
+        limit: usize,
+    ) -> LedgerResult<Vec<(EntryRef, Vec<String>)>> {
+        let layout = self.layouts.for_sheet(target);
+        let today = Local::now().date_naive();
+        let mut rows = vec![];
+        for month in [today, today - Months::new(1)] {
+            if rows.len() >= limit {
+                break;
+            }
+            // nothing was written that month
+            if !self.has_tab(target, &layout.month_tab(month)).await? {
+                continue;
+            }
+            let older =
+                get_recent_rows(&self.hub, target, layout, kind, month, limit - rows.len()).await?;
+            rows.extend(older);
+        }
+        Ok(rows)
+    }
+
+    async fn has_tab(&self, target: &str, tab: &str) -> LedgerResult<bool> {
+        let known = self
+            .known_tabs
+            .lock()
+            .unwrap()
+            .get(target)
+            .is_some_and(|tabs| tabs.contains(tab));
+        if known {
+            return Ok(true);
+        }
+
+        let tabs: HashSet<String> = get_tabs(&self.hub, target)
+            .await?
+            .into_iter()
+            .filter_map(|properties| properties.title)
+            .collect();
+        let found = tabs.contains(tab);
+        self.known_tabs
+            .lock()
+            .unwrap()
+            .insert(target.to_string(), tabs);
+        Ok(found)
+    }
+
+    /// Creates an empty `tab` unless the spreadsheet has it already.
+    async fn add_missing_tab(&self, target: &str, tab: &str) -> LedgerResult<()> {
+        if self.has_tab(target, tab).await? {
+            return Ok(());
+        }
+        create_tab(&self.hub, target, tab).await?;
+        self.known_tabs
+            .lock()
+            .unwrap()
+            .entry(target.to_string())
+            .or_default()
+            .insert(tab.to_string());
+        Ok(())
+    }
+
+    /// Makes sure the tab of the month of `date` exists, creating it from
+    /// the template the first time it's needed.
+    async fn ensure_month_tab(&self, target: &str, date: NaiveDate) -> LedgerResult<()> {
+        let layout = self.layouts.for_sheet(target);
+        let tab = layout.month_tab(date);
+        if self.has_tab(target, &tab).await? {
+            return Ok(());
+        }
+
+        // another write may have created it in the meantime
+        if let Err(error) = create_month_tab(&self.hub, target, layout, &tab).await {
+            return match self.has_tab(target, &tab).await {
+                Ok(true) => Ok(()),
+                _ => Err(error),
+            };
+        }
+        self.known_tabs
+            .lock()
+            .unwrap()
+            .entry(target.to_string())
+            .or_default()
+            .insert(tab);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LedgerBackend for SheetsLedger {
+    async fn write_expense(
+        &self,
+        target: &str,
+        data: &structs::PagamentoStruct,
+    ) -> LedgerResult<EntryRef> {
+        self.ensure_month_tab(target, data.date).await?;
+        write_pagamento_data(&self.hub, target, self.layouts.for_sheet(target), data).await
+    }
+
+    async fn write_income(
+        &self,
+        target: &str,
+        data: &structs::GuadagnoStruct,
+    ) -> LedgerResult<EntryRef> {
+        self.ensure_month_tab(target, data.date).await?;
+        write_guadagno_data(&self.hub, target, self.layouts.for_sheet(target), data).await
+    }
+
+    async fn recent_expenses(
+        &self,
+        target: &str,
+        limit: usize,
+    ) -> LedgerResult<Vec<(EntryRef, structs::PagamentoStruct)>> {
+        let rows = self.recent_rows(target, EntryKind::Expense, limit).await?;
+        Ok(readable(rows, pagamento_from_row))
+    }
+
+    async fn recent_incomes(
+        &self,
+        target: &str,
+        limit: usize,
+    ) -> LedgerResult<Vec<(EntryRef, structs::GuadagnoStruct)>> {
+        let rows = self.recent_rows(target, EntryKind::Income, limit).await?;
+        Ok(readable(rows, guadagno_from_row))
+    }
+
+    async fn expense(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+    ) -> LedgerResult<Option<structs::PagamentoStruct>> {
+        let row = get_entry_row(&self.hub, target, self.layouts.for_sheet(target), entry).await?;
+        row.map(|row| pagamento_from_row(entry.month, &row))
+            .transpose()
+    }
+
+    async fn income(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+    ) -> LedgerResult<Option<structs::GuadagnoStruct>> {
+        let row = get_entry_row(&self.hub, target, self.layouts.for_sheet(target), entry).await?;
+        row.map(|row| guadagno_from_row(entry.month, &row))
+            .transpose()
+    }
+
+    async fn update_expense(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+        data: &structs::PagamentoStruct,
+    ) -> LedgerResult<()> {
+        self.ensure_month_tab(target, data.date).await?;
+        update_pagamento_data(
+            &self.hub,
+            target,
+            self.layouts.for_sheet(target),
+            entry,
+            data,
+        )
+        .await
+    }
+
+    async fn update_income(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+        data: &structs::GuadagnoStruct,
+    ) -> LedgerResult<()> {
+        self.ensure_month_tab(target, data.date).await?;
+        update_guadagno_data(
+            &self.hub,
+            target,
+            self.layouts.for_sheet(target),
+            entry,
+            data,
+        )
+        .await
+    }
+
+    async fn delete_entry(&self, target: &str, entry: &EntryRef) -> LedgerResult<()> {
+        delete_entry_row(&self.hub, target, self.layouts.for_sheet(target), entry).await
+    }
+
+    fn stored_values(&self, target: &str, kind: EntryKind) -> usize {
+        self.layouts.for_sheet(target).columns(kind).width()
+    }
+
+    async fn categories(&self, target: &str) -> LedgerResult<Vec<String>> {
+        self.cached_list(target, ListKind::Categories).await
+    }
+
+    async fn wallets(&self, target: &str) -> LedgerResult<Vec<String>> {
+        self.cached_list(target, ListKind::Wallets).await
+    }
+
+    async fn income_categories(&self, target: &str) -> LedgerResult<Vec<String>> {
+        // sheets made before incomes had categories don't list any
+        let (range, _) = self
+            .layouts
+            .for_sheet(target)
+            .list_ranges(ListKind::IncomeCategories);
+        if !self.has_tab(target, &range_tab(range)).await? {
+            return Ok(vec![]);
+        }
+        self.cached_list(target, ListKind::IncomeCategories).await
+    }
+
+    async fn list(&self, target: &str, kind: ListKind) -> LedgerResult<NameList> {
+        let (active, archived) = self.layouts.for_sheet(target).list_ranges(kind);
+        let archived = if self.has_tab(target, &range_tab(archived)).await? {
+            get_list(&self.hub, target, archived).await?
+        } else {
+            vec![]
+        };
+        // read past the cache, since the list is about to be changed
+        let active = if self.has_tab(target, &range_tab(active)).await? {
+            get_list(&self.hub, target, active).await?
+        } else {
+            vec![]
+        };
+        self.lists
+            .lock()
+            .unwrap()
+            .insert((target.to_string(), kind), CachedList::new(active.clone()));
+        Ok(NameList { active, archived })
+    }
+
+    async fn save_list(&self, target: &str, kind: ListKind, list: &NameList) -> LedgerResult<()> {
+        let (active, archived) = self.layouts.for_sheet(target).list_ranges(kind);
+        self.add_missing_tab(target, &range_tab(active)).await?;
+        let mut data = vec![];
+        data.extend(list_values(&self.hub, target, active, &list.active).await?);
+        let archive_tab = range_tab(archived);
+        if !list.archived.is_empty() || self.has_tab(target, &archive_tab).await? {
+            self.add_missing_tab(target, &archive_tab).await?;
+            data.extend(list_values(&self.hub, target, archived, &list.archived).await?);
+        }
+
+        // both lists in one request, so a name moved from one to the other
+        // can't end up in neither
+        if !data.is_empty() {
+            let request = BatchUpdateValuesRequest {
+                data: Some(data),
+                value_input_option: Some("RAW".to_string()),
+                ..Default::default()
+            };
+            self.hub
+                .spreadsheets()
+                .values_batch_update(request, target)
+                .doit()
+                .await?;
+        }
+        self.lists.lock().unwrap().insert(
+            (target.to_string(), kind),
+            CachedList::new(list.active.clone()),
+        );
+        Ok(())
+    }
+
+    async fn refresh(&self, target: &str) {
+        self.lists
+            .lock()
+            .unwrap()
+            .retain(|(sheet_id, _), _| sheet_id != target);
+        self.known_tabs.lock().unwrap().remove(target);
+    }
+
+    async fn validate_target(&self, target: &str) -> LedgerResult<Vec<String>> {
+        let Some(sheet_id) = sheet_key(target) else {
+            return Ok(vec!["non è un link o un id di un foglio Google".to_string()]);
+        };
+        check_sheet(&self.hub, sheet_id, self.layouts.for_sheet(sheet_id)).await
+    }
+
+    async fn set_up(&self, target: &str) -> LedgerResult<()> {
+        let layout = self.layouts.for_sheet(target);
+        set_up_spreadsheet(&self.hub, target, layout, Local::now().year()).await?;
+        self.known_tabs.lock().unwrap().remove(target);
+        Ok(())
+    }
 }
 
-pub fn get_sheet_id(chat_id: ChatId) -> String {
-    let file_data = fs::read_to_string("user_data.json").unwrap();
-    let json_data: serde_json::Value = serde_json::from_str(&file_data).unwrap();
-    if let Some(sheet_id) = json_data.get(chat_id.to_string()) {
-        if let Some(sheet_id_string) = sheet_id.as_str() {
-            return sheet_id_string.to_string();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "1AbCdEfGhIjKlMnOpQrStUvWxYz_0123456789-ab";
+
+    #[test]
+    fn reads_the_key_of_a_link() {
+        assert_eq!(sheet_key(ID), Some(ID));
+        assert_eq!(sheet_key(&format!("  {}\n", ID)), Some(ID));
+        let links = [
+            format!("https://docs.google.com/spreadsheets/d/{}/edit#gid=0", ID),
+            format!("https://docs.google.com/spreadsheets/d/{}", ID),
+            format!("https://docs.google.com/spreadsheets/u/0/d/{}/edit", ID),
+            format!("https://docs.google.com/spreadsheets/d/{}?usp=sharing", ID),
+            format!(
+                "https://docs.google.com/spreadsheet/ccc?key={}&usp=sharing",
+                ID
+            ),
+            format!("https://drive.google.com/open?id={}", ID),
+        ];
+        for link in links {
+            assert_eq!(sheet_key(&link), Some(ID), "{}", link);
+        }
+    }
+
+    #[test]
+    fn refuses_what_is_not_a_key() {
+        assert_eq!(sheet_key(""), None);
+        assert_eq!(sheet_key("short"), None);
+        assert_eq!(sheet_key("not a spreadsheet id at all, really"), None);
+        assert_eq!(sheet_key("https://example.com/?q=1"), None);
+        assert_eq!(
+            sheet_key("https://docs.google.com/spreadsheets/d/e/2PACX/pubhtml"),
+            None
+        );
+    }
+
+    #[test]
+    fn finds_the_row_of_a_range() {
+        assert_eq!(range_row("March!B12:G12").unwrap(), 12);
+        assert_eq!(range_row("B4").unwrap(), 4);
+        assert!(range_row("March!B:G").is_err());
+    }
+
+    #[test]
+    fn refuses_rows_it_cannot_read_back() {
+        let month = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+        let row = |amount: &str, day: &str| -> Vec<String> {
+            ["Spesa", amount, day, "Food", "Cash", ""]
+                .map(str::to_string)
+                .to_vec()
+        };
+        let p_data = pagamento_from_row(month, &row("€12.50", "18")).unwrap();
+        assert_eq!(p_data.amount.cents(), 1250);
+        assert_eq!(p_data.date, NaiveDate::from_ymd_opt(2026, 10, 18).unwrap());
+        for amount in ["-€12.50", "(12.50)", "EUR 12.50", "1.859"] {
+            assert!(pagamento_from_row(month, &row(amount, "18")).is_err());
+            assert!(guadagno_from_row(month, &row(amount, "18")).is_err());
         }
+        assert!(pagamento_from_row(month, &row("12.50", "32")).is_err());
+        assert!(guadagno_from_row(month, &row("12.50", "")).is_err());
     }
-    return "".to_string();
 }
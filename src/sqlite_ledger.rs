@@ -0,0 +1,599 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row};
+
+use crate::ledger::{
+    EntryKind, EntryRef, LedgerBackend, LedgerResult, ListKind, NameList, DEFAULT_CATEGORIES,
+    DEFAULT_INCOME_CATEGORIES, DEFAULT_WALLETS,
+};
+use crate::{money::Money, structs};
+
+static SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS expenses (
+        id INTEGER PRIMARY KEY,
+        ledger TEXT NOT NULL,
+        title TEXT NOT NULL,
+        amount_cents INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        category TEXT NOT NULL,
+        wallet TEXT NOT NULL,
+        notes TEXT NOT NULL,
+        subcategory TEXT NOT NULL DEFAULT '',
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+    CREATE TABLE IF NOT EXISTS incomes (
+        id INTEGER PRIMARY KEY,
+        ledger TEXT NOT NULL,
+        title TEXT NOT NULL,
+        amount_cents INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        category TEXT NOT NULL DEFAULT '',
+        wallet TEXT NOT NULL DEFAULT '',
+        notes TEXT NOT NULL DEFAULT '',
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+";
+
+/// Columns of the tables holding a list, kept apart for each ledger like
+/// the entries.
+static LIST_COLUMNS: &str = "
+    id INTEGER PRIMARY KEY,
+    ledger TEXT NOT NULL,
+    name TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    archived INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (ledger, name)
+";
+
+static LIST_KINDS: [ListKind; 3] = [
+    ListKind::Categories,
+    ListKind::Wallets,
+    ListKind::IncomeCategories,
+];
+
+/// Table holding a list, and the names it starts with.
+fn list_table(kind: ListKind) -> (&'static str, &'static [&'static str]) {
+    match kind {
+        ListKind::Categories => ("categories", &DEFAULT_CATEGORIES),
+        ListKind::Wallets => ("wallets", &DEFAULT_WALLETS),
+        ListKind::IncomeCategories => ("income_categories", &DEFAULT_INCOME_CATEGORIES),
+    }
+}
+
+/// Columns added after the tables were first created, added on opening to
+/// databases that predate them.
+static ADDED_COLUMNS: [(&str, &str, &str); 8] = [
+    ("categories", "archived", "INTEGER NOT NULL DEFAULT 0"),
+    ("wallets", "archived", "INTEGER NOT NULL DEFAULT 0"),
+    ("expenses", "subcategory", "TEXT NOT NULL DEFAULT ''"),
+    ("incomes", "category", "TEXT NOT NULL DEFAULT ''"),
+    ("incomes", "wallet", "TEXT NOT NULL DEFAULT ''"),
+    ("incomes", "notes", "TEXT NOT NULL DEFAULT ''"),
+    ("expenses", "amount_cents", "INTEGER NOT NULL DEFAULT 0"),
+    ("incomes", "amount_cents", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// Tables whose amounts were once kept in euros in a REAL `amount` column,
+/// moved to `amount_cents` on opening.
+static CENTS_TABLES: [&str; 2] = ["expenses", "incomes"];
+
+static EXPENSE_COLUMNS: &str =
+    "id, title, amount_cents, date, category, wallet, notes, subcategory";
+static INCOME_COLUMNS: &str = "id, title, amount_cents, date, category, wallet, notes";
+
+fn read_date(row: &Row, index: usize) -> rusqlite::Result<NaiveDate> {
+    let text: String = row.get(index)?;
+    NaiveDate::parse_from_str(&text, "%Y-%m-%d")
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn read_amount(row: &Row, index: usize) -> rusqlite::Result<Money> {
+    Ok(Money::from_cents(row.get(index)?))
+}
+
+/// Reads a row selected with [`EXPENSE_COLUMNS`].
+fn read_expense(row: &Row) -> rusqlite::Result<(EntryRef, structs::PagamentoStruct)> {
+    let date = read_date(row, 3)?;
+    let data = structs::PagamentoStruct {
+        title: row.get(1)?,
+        amount: read_amount(row, 2)?,
+        date,
+        category: row.get(4)?,
+        subcategory: row.get(7)?,
+        wallet: row.get(5)?,
+        notes: row.get(6)?,
+        missing: vec![],
+        recorded: None,
+        recorded_summary: String::new(),
+    };
+    Ok((EntryRef::new(EntryKind::Expense, date, row.get(0)?), data))
+}
+
+/// Reads a row selected with [`INCOME_COLUMNS`].
+fn read_income(row: &Row) -> rusqlite::Result<(EntryRef, structs::GuadagnoStruct)> {
+    let date = read_date(row, 3)?;
+    let data = structs::GuadagnoStruct {
+        title: row.get(1)?,
+        amount: read_amount(row, 2)?,
+        date,
+        category: row.get(4)?,
+        wallet: row.get(5)?,
+        notes: row.get(6)?,
+        missing: vec![],
+        recorded: None,
+        recorded_summary: String::new(),
+    };
+    Ok((EntryRef::new(EntryKind::Income, date, row.get(0)?), data))
+}
+
+/// Local [`LedgerBackend`] keeping every chat's entries and lists in a
+/// single SQLite file. The target only tags rows, so no /link is needed.
+pub struct SqliteLedger {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteLedger {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        for kind in LIST_KINDS {
+            let (table, _) = list_table(kind);
+            conn.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} ({});",
+                table, LIST_COLUMNS
+            ))?;
+        }
+        for (table, column, definition) in ADDED_COLUMNS {
+            if conn
+                .prepare(&format!("SELECT {} FROM {}", column, table))
+                .is_err()
+            {
+                conn.execute(
+                    &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                    [],
+                )?;
+            }
+        }
+        for table in CENTS_TABLES {
+            if conn
+                .prepare(&format!("SELECT amount FROM {}", table))
+                .is_ok()
+            {
+                conn.execute_batch(&format!(
+                    "BEGIN;
+                     UPDATE {table} SET amount_cents = CAST(ROUND(amount * 100) AS INTEGER);
+                     ALTER TABLE {table} DROP COLUMN amount;
+                     COMMIT;",
+                    table = table
+                ))?;
+            }
+        }
+        // lists used to be shared by every ledger: each one gets a copy
+        for kind in LIST_KINDS {
+            let (table, _) = list_table(kind);
+            if conn
+                .prepare(&format!("SELECT ledger FROM {}", table))
+                .is_err()
+            {
+                conn.execute_batch(&format!(
+                    "BEGIN;
+                     ALTER TABLE {table} RENAME TO shared_{table};
+                     CREATE TABLE {table} ({columns});
+                     INSERT INTO {table} (ledger, name, position, archived)
+                         SELECT ledgers.ledger, name, position, archived
+                         FROM shared_{table},
+                             (SELECT ledger FROM expenses UNION SELECT ledger FROM incomes)
+                                 AS ledgers;
+                     DROP TABLE shared_{table};
+                     COMMIT;",
+                    table = table,
+                    columns = LIST_COLUMNS
+                ))?;
+            }
+        }
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Reads the names in use of a list of `target`, or the archived ones,
+    /// filling the list with its defaults the first time it is found empty.
+    fn get_list(
+        &self,
+        target: &str,
+        kind: ListKind,
+        archived: bool,
+    ) -> rusqlite::Result<Vec<String>> {
+        let (table, defaults) = list_table(kind);
+        let conn = self.conn.lock().unwrap();
+
+        let count: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE ledger = ?1", table),
+            [target],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            let insert = format!(
+                "INSERT INTO {} (ledger, name, position) VALUES (?1, ?2, ?3)",
+                table
+            );
+            for (position, name) in defaults.iter().enumerate() {
+                conn.execute(&insert, params![target, name, position as i64])?;
+            }
+        }
+
+        let mut statement = conn.prepare(&format!(
+            "SELECT name FROM {} WHERE ledger = ?1 AND archived = ?2 ORDER BY position",
+            table
+        ))?;
+        let names = statement
+            .query_map(params![target, archived], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(names)
+    }
+}
+
+#[async_trait]
+impl LedgerBackend for SqliteLedger {
+    async fn write_expense(
+        &self,
+        target: &str,
+        data: &structs::PagamentoStruct,
+    ) -> LedgerResult<EntryRef> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO expenses
+                 (ledger, title, amount_cents, date, category, wallet, notes, subcategory)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                target,
+                data.title,
+                data.amount.cents(),
+                data.date.to_string(),
+                data.category,
+                data.wallet,
+                data.notes,
+                data.subcategory,
+            ],
+        )?;
+        let row = conn.last_insert_rowid();
+        Ok(EntryRef::new(EntryKind::Expense, data.date, row))
+    }
+
+    async fn write_income(
+        &self,
+        target: &str,
+        data: &structs::GuadagnoStruct,
+    ) -> LedgerResult<EntryRef> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO incomes (ledger, title, amount_cents, date, category, wallet, notes)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                target,
+                data.title,
+                data.amount.cents(),
+                data.date.to_string(),
+                data.category,
+                data.wallet,
+                data.notes,
+            ],
+        )?;
+        let row = conn.last_insert_rowid();
+        Ok(EntryRef::new(EntryKind::Income, data.date, row))
+    }
+
+    async fn recent_expenses(
+        &self,
+        target: &str,
+        limit: usize,
+    ) -> LedgerResult<Vec<(EntryRef, structs::PagamentoStruct)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM expenses WHERE ledger = ?1 ORDER BY id DESC LIMIT ?2",
+            EXPENSE_COLUMNS
+        ))?;
+        let entries = statement
+            .query_map(params![target, limit as i64], read_expense)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    async fn recent_incomes(
+        &self,
+        target: &str,
+        limit: usize,
+    ) -> LedgerResult<Vec<(EntryRef, structs::GuadagnoStruct)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM incomes WHERE ledger = ?1 ORDER BY id DESC LIMIT ?2",
+            INCOME_COLUMNS
+        ))?;
+        let entries = statement
+            .query_map(params![target, limit as i64], read_income)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    async fn expense(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+    ) -> LedgerResult<Option<structs::PagamentoStruct>> {
+        let conn = self.conn.lock().unwrap();
+        let expense = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM expenses WHERE ledger = ?1 AND id = ?2",
+                    EXPENSE_COLUMNS
+                ),
+                params![target, entry.row],
+                read_expense,
+            )
+            .optional()?;
+        Ok(expense.map(|(_, data)| data))
+    }
+
+    async fn income(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+    ) -> LedgerResult<Option<structs::GuadagnoStruct>> {
+        let conn = self.conn.lock().unwrap();
+        let income = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM incomes WHERE ledger = ?1 AND id = ?2",
+                    INCOME_COLUMNS
+                ),
+                params![target, entry.row],
+                read_income,
+            )
+            .optional()?;
+        Ok(income.map(|(_, data)| data))
+    }
+
+    async fn update_expense(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+        data: &structs::PagamentoStruct,
+    ) -> LedgerResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE expenses
+             SET title = ?3, amount_cents = ?4, date = ?5, category = ?6, wallet = ?7, notes = ?8,
+                 subcategory = ?9
+             WHERE ledger = ?1 AND id = ?2",
+            params![
+                target,
+                entry.row,
+                data.title,
+                data.amount.cents(),
+                data.date.to_string(),
+                data.category,
+                data.wallet,
+                data.notes,
+                data.subcategory,
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn update_income(
+        &self,
+        target: &str,
+        entry: &EntryRef,
+        data: &structs::GuadagnoStruct,
+    ) -> LedgerResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE incomes
+             SET title = ?3, amount_cents = ?4, date = ?5, category = ?6, wallet = ?7, notes = ?8
+             WHERE ledger = ?1 AND id = ?2",
+            params![
+                target,
+                entry.row,
+                data.title,
+                data.amount.cents(),
+                data.date.to_string(),
+                data.category,
+                data.wallet,
+                data.notes,
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn delete_entry(&self, target: &str, entry: &EntryRef) -> LedgerResult<()> {
+        let table = match entry.kind {
+            EntryKind::Expense => "expenses",
+            EntryKind::Income => "incomes",
+        };
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("DELETE FROM {} WHERE ledger = ?1 AND id = ?2", table),
+            params![target, entry.row],
+        )?;
+        Ok(())
+    }
+
+    // every field has its own column
+    fn stored_values(&self, _target: &str, _kind: EntryKind) -> usize {
+        usize::MAX
+    }
+
+    async fn categories(&self, target: &str) -> LedgerResult<Vec<String>> {
+        Ok(self.get_list(target, ListKind::Categories, false)?)
+    }
+
+    async fn wallets(&self, target: &str) -> LedgerResult<Vec<String>> {
+        Ok(self.get_list(target, ListKind::Wallets, false)?)
+    }
+
+    async fn income_categories(&self, target: &str) -> LedgerResult<Vec<String>> {
+        Ok(self.get_list(target, ListKind::IncomeCategories, false)?)
+    }
+
+    async fn list(&self, target: &str, kind: ListKind) -> LedgerResult<NameList> {
+        Ok(NameList {
+            active: self.get_list(target, kind, false)?,
+            archived: self.get_list(target, kind, true)?,
+        })
+    }
+
+    async fn save_list(&self, target: &str, kind: ListKind, list: &NameList) -> LedgerResult<()> {
+        let (table, _) = list_table(kind);
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        transaction.execute(
+            &format!("DELETE FROM {} WHERE ledger = ?1", table),
+            [target],
+        )?;
+        let insert = format!(
+            "INSERT INTO {} (ledger, name, position, archived) VALUES (?1, ?2, ?3, ?4)",
+            table
+        );
+        let names = (list.active.iter().map(|name| (name, false)))
+            .chain(list.archived.iter().map(|name| (name, true)));
+        for (position, (name, archived)) in names.enumerate() {
+            transaction.execute(&insert, params![target, name, position as i64, archived])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // nothing is cached, the database is local
+    async fn refresh(&self, _target: &str) {}
+
+    async fn validate_target(&self, _target: &str) -> LedgerResult<Vec<String>> {
+        Ok(vec![])
+    }
+
+    // the tables are created when the database is opened
+    async fn set_up(&self, _target: &str) -> LedgerResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path of a new database for the test called `name`.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn keeps_each_target_apart() {
+        let path = temp_path("ledger-apart");
+        let ledger = SqliteLedger::open(&path).unwrap();
+        ledger
+            .write_expense("1", &structs::PagamentoStruct::new())
+            .await
+            .unwrap();
+        let list = NameList {
+            active: names(&["Home"]),
+            archived: names(&["Food"]),
+        };
+        ledger
+            .save_list("1", ListKind::Categories, &list)
+            .await
+            .unwrap();
+
+        assert_eq!(ledger.recent_expenses("1", 10).await.unwrap().len(), 1);
+        assert!(ledger.recent_expenses("2", 10).await.unwrap().is_empty());
+        assert_eq!(ledger.categories("1").await.unwrap(), names(&["Home"]));
+        assert_eq!(
+            ledger.categories("2").await.unwrap(),
+            names(&DEFAULT_CATEGORIES)
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_every_ledger_a_copy_of_a_shared_list() {
+        let path = temp_path("ledger-shared");
+        let old = Connection::open(&path).unwrap();
+        old.execute_batch(
+            "CREATE TABLE wallets (
+                 id INTEGER PRIMARY KEY,
+                 name TEXT NOT NULL UNIQUE,
+                 position INTEGER NOT NULL
+             );
+             INSERT INTO wallets (name, position) VALUES ('Cash', 0), ('Bank', 1);
+             CREATE TABLE incomes (
+                 id INTEGER PRIMARY KEY,
+                 ledger TEXT NOT NULL,
+                 title TEXT NOT NULL,
+                 amount_cents INTEGER NOT NULL,
+                 date TEXT NOT NULL
+             );
+             INSERT INTO incomes (ledger, title, amount_cents, date)
+             VALUES ('1', 'stipendio', 100000, '2026-10-01'),
+                    ('2', 'stipendio', 100000, '2026-10-01');",
+        )
+        .unwrap();
+        drop(old);
+
+        let ledger = SqliteLedger::open(&path).unwrap();
+        let list = NameList {
+            active: names(&["Cash"]),
+            archived: names(&["Bank"]),
+        };
+        ledger
+            .save_list("1", ListKind::Wallets, &list)
+            .await
+            .unwrap();
+        assert_eq!(ledger.wallets("1").await.unwrap(), names(&["Cash"]));
+        assert_eq!(ledger.wallets("2").await.unwrap(), names(&["Cash", "Bank"]));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn moves_old_amounts_to_cents() {
+        let path = temp_path("ledger-cents");
+        let path = path.as_str();
+        let old = Connection::open(path).unwrap();
+        old.execute_batch(
+            "CREATE TABLE expenses (
+                 id INTEGER PRIMARY KEY,
+                 ledger TEXT NOT NULL,
+                 title TEXT NOT NULL,
+                 amount REAL NOT NULL,
+                 date TEXT NOT NULL,
+                 category TEXT NOT NULL,
+                 wallet TEXT NOT NULL,
+                 notes TEXT NOT NULL,
+                 created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+             );
+             INSERT INTO expenses (ledger, title, amount, date, category, wallet, notes)
+             VALUES ('1', 'caffè', 1.1, '2026-10-18', 'Food', 'Cash', '');",
+        )
+        .unwrap();
+        drop(old);
+
+        let ledger = SqliteLedger::open(path).unwrap();
+        let mut data = structs::PagamentoStruct::new();
+        data.amount = Money::from_cents(1999);
+        ledger.write_expense("1", &data).await.unwrap();
+        let amounts: Vec<i64> = ledger
+            .recent_expenses("1", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, data)| data.amount.cents())
+            .collect();
+        assert_eq!(amounts, vec![1999, 110]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,34 +1,176 @@
-use google_sheets4::Sheets;
-use google_sheets4::{hyper, hyper_rustls};
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+use crate::{categories, ledger::EntryRef, money::Money};
+
+/// Field of an entry the dialogue still has to ask for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Field {
+    Title,
+    Amount,
+    Date,
+    Category,
+    Wallet,
+    Notes,
+}
+
+impl Field {
+    pub fn key(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Amount => "amount",
+            Field::Date => "date",
+            Field::Category => "category",
+            Field::Wallet => "wallet",
+            Field::Notes => "notes",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "title" => Some(Field::Title),
+            "amount" => Some(Field::Amount),
+            "date" => Some(Field::Date),
+            "category" => Some(Field::Category),
+            "wallet" => Some(Field::Wallet),
+            "notes" => Some(Field::Notes),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Field::Title => "titolo",
+            Field::Amount => "importo",
+            Field::Date => "data",
+            Field::Category => "categoria",
+            Field::Wallet => "wallet",
+            Field::Notes => "note",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PagamentoStruct {
     pub title: String,
-    pub amount: f32,
-    pub date: u8,
+    pub amount: Money,
+    pub date: NaiveDate,
     pub category: String,
+    /// Second level of the category, empty if it has none.
+    #[serde(default)]
+    pub subcategory: String,
     pub wallet: String,
     pub notes: String,
+    /// Fields still to ask, in order.
+    #[serde(default)]
+    pub missing: Vec<Field>,
+    /// Where the entry was written, when editing one from /recent.
+    #[serde(default)]
+    pub recorded: Option<EntryRef>,
+    /// Summary of the recorded entry when it was read, to check its row
+    /// still holds it before saving over it.
+    #[serde(default)]
+    pub recorded_summary: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GuadagnoStruct {
     pub title: String,
-    pub amount: f32,
-    pub date: u8,
+    pub amount: Money,
+    pub date: NaiveDate,
+    /// Empty when the ledger has no income categories.
+    #[serde(default)]
+    pub category: String,
+    /// Where the income was received.
+    #[serde(default)]
+    pub wallet: String,
+    #[serde(default)]
+    pub notes: String,
+    /// Fields still to ask, in order.
+    #[serde(default)]
+    pub missing: Vec<Field>,
+    /// Where the entry was written, when editing one from /recent.
+    #[serde(default)]
+    pub recorded: Option<EntryRef>,
+    /// Summary of the recorded entry when it was read, to check its row
+    /// still holds it before saving over it.
+    #[serde(default)]
+    pub recorded_summary: String,
 }
 
 impl PagamentoStruct {
+    pub const FIELDS: [Field; 6] = [
+        Field::Title,
+        Field::Amount,
+        Field::Date,
+        Field::Category,
+        Field::Wallet,
+        Field::Notes,
+    ];
+
     pub fn new() -> Self {
         Self {
             title: "".to_string(),
-            amount: 0.0,
-            date: 0,
+            amount: Money::default(),
+            date: Local::now().date_naive(),
             category: "".to_string(),
+            subcategory: "".to_string(),
             wallet: "".to_string(),
             notes: "".to_string(),
+            missing: Self::FIELDS.to_vec(),
+            recorded: None,
+            recorded_summary: String::new(),
         }
     }
+
+    pub fn fill(&mut self, field: Field) {
+        self.missing.retain(|missing| *missing != field);
+    }
+
+    /// Fields a ledger with room for `values` values of an entry keeps, as
+    /// `FIELDS` follows the order of the columns.
+    pub fn stored_fields(values: usize) -> &'static [Field] {
+        &Self::FIELDS[..values.min(Self::FIELDS.len())]
+    }
+
+    /// Whether a ledger with room for `values` values of an entry keeps the
+    /// subcategory, written after all the other fields.
+    pub fn stores_subcategory(values: usize) -> bool {
+        values > Self::FIELDS.len()
+    }
+
+    /// The entry as a ledger with room for `values` values keeps it, without
+    /// the fields it has no column for, which are no longer asked either.
+    pub fn stored(&self, values: usize) -> Self {
+        let fields = Self::stored_fields(values);
+        let mut stored = self.clone();
+        stored.missing.retain(|field| fields.contains(field));
+        if !fields.contains(&Field::Category) {
+            stored.category.clear();
+        }
+        if !fields.contains(&Field::Wallet) {
+            stored.wallet.clear();
+        }
+        if !fields.contains(&Field::Notes) {
+            stored.notes.clear();
+        }
+        if !Self::stores_subcategory(values) {
+            stored.subcategory.clear();
+        }
+        stored
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "titolo: {}\nimporto: {}\ndata: {}\ncategoria: {}\nwallet: {}\nnote: {}",
+            self.title,
+            self.amount,
+            self.date.format("%d/%m/%Y"),
+            categories::join(&self.category, &self.subcategory),
+            self.wallet,
+            self.notes
+        )
+    }
 }
 impl Default for PagamentoStruct {
     fn default() -> Self {
@@ -37,13 +179,68 @@ impl Default for PagamentoStruct {
 }
 
 impl GuadagnoStruct {
+    pub const FIELDS: [Field; 6] = [
+        Field::Title,
+        Field::Amount,
+        Field::Date,
+        Field::Category,
+        Field::Wallet,
+        Field::Notes,
+    ];
+
     pub fn new() -> Self {
         Self {
             title: "".to_string(),
-            amount: 0.0,
-            date: 0,
+            amount: Money::default(),
+            date: Local::now().date_naive(),
+            category: "".to_string(),
+            wallet: "".to_string(),
+            notes: "".to_string(),
+            missing: Self::FIELDS.to_vec(),
+            recorded: None,
+            recorded_summary: String::new(),
         }
     }
+
+    pub fn fill(&mut self, field: Field) {
+        self.missing.retain(|missing| *missing != field);
+    }
+
+    /// Fields a ledger with room for `values` values of an entry keeps, as
+    /// `FIELDS` follows the order of the columns.
+    pub fn stored_fields(values: usize) -> &'static [Field] {
+        &Self::FIELDS[..values.min(Self::FIELDS.len())]
+    }
+
+    /// The entry as a ledger with room for `values` values keeps it, without
+    /// the fields it has no column for, which are no longer asked either.
+    pub fn stored(&self, values: usize) -> Self {
+        let fields = Self::stored_fields(values);
+        let mut stored = self.clone();
+        stored.missing.retain(|field| fields.contains(field));
+        if !fields.contains(&Field::Category) {
+            stored.category.clear();
+        }
+        if !fields.contains(&Field::Wallet) {
+            stored.wallet.clear();
+        }
+        if !fields.contains(&Field::Notes) {
+            stored.notes.clear();
+        }
+        stored
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "titolo: {}\nimporto: {}\ndata: {}\ncategoria: {}\nwallet: {}\nnote: {}",
+            self.title,
+            self.amount,
+            self.date.format("%d/%m/%Y"),
+            self.category,
+            self.wallet,
+            self.notes
+        )
+    }
 }
 impl Default for GuadagnoStruct {
     fn default() -> Self {
@@ -51,16 +248,36 @@ impl Default for GuadagnoStruct {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SheetData {
-    pub sheet: Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
     pub sheet_id: String,
 }
 impl SheetData {
-    pub fn new(
-        sheet: Sheets<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
-        sheet_id: String,
-    ) -> Self {
-        Self { sheet, sheet_id }
+    pub fn new(sheet_id: String) -> Self {
+        Self { sheet_id }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_only_the_fields_with_a_column() {
+        let mut g_data = GuadagnoStruct::new();
+        g_data.title = "stipendio".to_string();
+        g_data.wallet = "Bank".to_string();
+        g_data.notes = "ottobre".to_string();
+        let stored = g_data.stored(3);
+        assert_eq!(stored.title, "stipendio");
+        assert!(stored.wallet.is_empty() && stored.notes.is_empty());
+        assert_eq!(stored.missing, GuadagnoStruct::FIELDS[..3]);
+
+        let mut p_data = PagamentoStruct::new();
+        p_data.category = "Food".to_string();
+        p_data.subcategory = "Restaurants".to_string();
+        assert!(p_data.stored(6).subcategory.is_empty());
+        assert_eq!(p_data.stored(6).category, "Food");
+        assert_eq!(p_data.stored(7).subcategory, "Restaurants");
     }
 }
@@ -0,0 +1,267 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::{requests::Requester, types::ChatId, Bot};
+
+use crate::{
+    ledger::{EntryKind, EntryRef, LedgerResult, SharedLedger},
+    settings::{LastWrite, SettingsStore},
+    structs,
+};
+
+/// How often the background task looks for writes to retry.
+const TICK: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Attempts after which a queued write is given up, about a day of retries.
+const MAX_ATTEMPTS: u32 = 30;
+
+/// Entry waiting to be written, with what's needed to write it later.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum QueuedEntry {
+    Expense(structs::PagamentoStruct),
+    Income(structs::GuadagnoStruct),
+}
+
+impl QueuedEntry {
+    fn summary(&self) -> String {
+        match self {
+            QueuedEntry::Expense(p_data) => p_data.summary(),
+            QueuedEntry::Income(g_data) => g_data.summary(),
+        }
+    }
+
+    /// Summary of the fields `target` keeps, as read back from it.
+    fn stored_summary(&self, ledger: &SharedLedger, target: &str) -> String {
+        match self {
+            QueuedEntry::Expense(p_data) => p_data
+                .stored(ledger.stored_values(target, EntryKind::Expense))
+                .summary(),
+            QueuedEntry::Income(g_data) => g_data
+                .stored(ledger.stored_values(target, EntryKind::Income))
+                .summary(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct QueuedWrite {
+    id: u64,
+    chat_id: ChatId,
+    target: String,
+    entry: QueuedEntry,
+    attempts: u32,
+    next_attempt: DateTime<Utc>,
+}
+
+/// Wait before the next attempt: 30 seconds doubling at every failure, up
+/// to an hour.
+fn backoff(attempts: u32) -> Duration {
+    Duration::seconds(30 * 2_i64.pow(attempts.min(7))).min(Duration::hours(1))
+}
+
+/// Writes that failed because the ledger couldn't be reached, kept in a
+/// JSON file (saved like the chat settings) so they survive restarts.
+pub struct WriteQueue {
+    path: PathBuf,
+    writes: Mutex<Vec<QueuedWrite>>,
+}
+
+impl WriteQueue {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Arc<Self>> {
+        let path = path.into();
+        let writes = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            vec![]
+        };
+        Ok(Arc::new(Self {
+            path,
+            writes: Mutex::new(writes),
+        }))
+    }
+
+    pub fn push(&self, chat_id: ChatId, target: &str, entry: QueuedEntry) -> io::Result<()> {
+        let mut writes = self.writes.lock().unwrap();
+        let id = writes.iter().map(|write| write.id + 1).max().unwrap_or(0);
+        writes.push(QueuedWrite {
+            id,
+            chat_id,
+            target: target.to_string(),
+            entry,
+            attempts: 0,
+            next_attempt: Utc::now() + backoff(0),
+        });
+        self.save(&writes)
+    }
+
+    fn save(&self, writes: &[QueuedWrite]) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(writes)?)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    fn due(&self) -> Vec<QueuedWrite> {
+        let now = Utc::now();
+        let writes = self.writes.lock().unwrap();
+        writes
+            .iter()
+            .filter(|write| write.next_attempt <= now)
+            .cloned()
+            .collect()
+    }
+
+    fn remove(&self, id: u64) -> io::Result<()> {
+        let mut writes = self.writes.lock().unwrap();
+        writes.retain(|write| write.id != id);
+        self.save(&writes)
+    }
+
+    fn postpone(&self, id: u64) -> io::Result<()> {
+        let mut writes = self.writes.lock().unwrap();
+        if let Some(write) = writes.iter_mut().find(|write| write.id == id) {
+            write.attempts += 1;
+            write.next_attempt = Utc::now() + backoff(write.attempts);
+        }
+        self.save(&writes)
+    }
+}
+
+async fn write(ledger: &SharedLedger, write: &QueuedWrite) -> LedgerResult<EntryRef> {
+    match &write.entry {
+        QueuedEntry::Expense(p_data) => ledger.write_expense(&write.target, p_data).await,
+        QueuedEntry::Income(g_data) => ledger.write_income(&write.target, g_data).await,
+    }
+}
+
+/// Background task retrying the queued writes, telling each chat when its
+/// entry is finally saved, or dropped because the ledger rejects it or kept
+/// failing for too long.
+pub async fn run(
+    queue: Arc<WriteQueue>,
+    ledger: SharedLedger,
+    settings: Arc<SettingsStore>,
+    bot: Bot,
+) {
+    loop {
+        tokio::time::sleep(TICK).await;
+
+        for queued in queue.due() {
+            match write(&ledger, &queued).await {
+                Ok(entry) => {
+                    let summary = queued.entry.stored_summary(&ledger, &queued.target);
+                    if let Err(e) = queue.remove(queued.id) {
+                        log::error!("write queue not saved: {}", e);
+                    }
+                    if let Err(e) = settings.update(queued.chat_id, |chat| {
+                        chat.last_write = Some(LastWrite::new(entry, summary.clone()));
+                        match &queued.entry {
+                            QueuedEntry::Expense(p_data) => chat.record_use(
+                                &p_data.category,
+                                &p_data.subcategory,
+                                &p_data.wallet,
+                            ),
+                            QueuedEntry::Income(g_data) => {
+                                chat.record_income_use(&g_data.category, &g_data.wallet)
+                            }
+                        }
+                    }) {
+                        log::error!("chat settings not saved: {}", e);
+                    }
+                    let text = format!("✅ salvato il movimento in coda:\n{}", summary);
+                    if let Err(e) = bot.send_message(queued.chat_id, text).await {
+                        log::error!("queued write notification not sent: {}", e);
+                    }
+                }
+                Err(error) if error.is_transient() && queued.attempts + 1 < MAX_ATTEMPTS => {
+                    log::warn!(
+                        "queued write {} failed (attempt {}): {}",
+                        queued.id,
+                        queued.attempts + 1,
+                        error
+                    );
+                    if let Err(e) = queue.postpone(queued.id) {
+                        log::error!("write queue not saved: {}", e);
+                    }
+                }
+                Err(error) => {
+                    log::error!(
+                        "queued write {} dropped (attempt {}): {}",
+                        queued.id,
+                        queued.attempts + 1,
+                        error
+                    );
+                    if let Err(e) = queue.remove(queued.id) {
+                        log::error!("write queue not saved: {}", e);
+                    }
+                    let text = format!(
+                        "❌ non sono riuscito a salvare il movimento in coda ({}), rimandalo:\n{}",
+                        error,
+                        queued.entry.summary()
+                    );
+                    if let Err(e) = bot.send_message(queued.chat_id, text).await {
+                        log::error!("queued write notification not sent: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_up_to_an_hour() {
+        assert_eq!(backoff(0), Duration::seconds(30));
+        assert_eq!(backoff(1), Duration::seconds(60));
+        assert_eq!(backoff(6), Duration::seconds(1920));
+        assert_eq!(backoff(7), Duration::hours(1));
+        assert_eq!(backoff(40), Duration::hours(1));
+    }
+
+    #[test]
+    fn gives_up_after_about_a_day() {
+        let total = (0..MAX_ATTEMPTS).fold(Duration::zero(), |total, attempts| {
+            total + backoff(attempts)
+        });
+        assert!(total > Duration::hours(20) && total < Duration::hours(28));
+    }
+
+    #[test]
+    fn keeps_the_queue_across_restarts() {
+        let path = std::env::temp_dir().join(format!("write-queue-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let queue = WriteQueue::open(&path).unwrap();
+        for title in ["caffè", "pranzo", "cena"] {
+            let mut p_data = structs::PagamentoStruct::new();
+            p_data.title = title.to_string();
+            queue
+                .push(ChatId(1), "sheet", QueuedEntry::Expense(p_data))
+                .unwrap();
+        }
+        queue.remove(0).unwrap();
+        queue.postpone(2).unwrap();
+        drop(queue);
+
+        let queue = WriteQueue::open(&path).unwrap();
+        let writes = queue.writes.lock().unwrap();
+        let kept: Vec<(u64, u32)> = writes
+            .iter()
+            .map(|write| (write.id, write.attempts))
+            .collect();
+        assert_eq!(kept, vec![(1, 0), (2, 1)]);
+        assert!(matches!(
+            &writes[0].entry,
+            QueuedEntry::Expense(p_data) if p_data.title == "pranzo"
+        ));
+        assert_eq!(writes[0].target, "sheet");
+        drop(writes);
+        fs::remove_file(&path).unwrap();
+    }
+}